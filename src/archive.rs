@@ -0,0 +1,83 @@
+use crate::videolist::youtube_video_id;
+use log::{debug, info};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use url::Url;
+
+/// The download archive, a text file with a `<extractor> <video id>` line per downloaded video. This is the format
+/// of the yt-dlp `--download-archive` file, so the same file can be shared with yt-dlp runs outside yt-parallel.
+pub struct Archive {
+    path: PathBuf,
+    keys: Mutex<HashSet<String>>,
+}
+
+impl Archive {
+    /// Load the archive, a file that does not exist yet is an empty archive, it is created on the first download.
+    /// # Parameters
+    /// path - The location of the archive file
+    /// # Returns
+    /// The archive, or the error when the file exists but cannot be read.
+    pub fn load(path: &Path) -> io::Result<Archive> {
+        let keys: HashSet<String> = if path.exists() {
+            fs::read_to_string(path)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect()
+        } else {
+            debug!("No archive at {} yet, starting empty", path.display());
+            HashSet::new()
+        };
+        info!(
+            "Download archive {} has {} entries",
+            path.display(),
+            keys.len()
+        );
+        Ok(Archive {
+            path: path.to_path_buf(),
+            keys: Mutex::new(keys),
+        })
+    }
+
+    /// Whether the video with the key has been downloaded before
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys
+            .lock()
+            .expect("Archive lock poisoned")
+            .contains(key)
+    }
+
+    /// Add a downloaded video to the archive, the line is appended to the file right away so a crash does not lose it.
+    /// # Parameters
+    /// key - The `<extractor> <video id>` of the video
+    /// # Returns
+    /// Nothing on ok, the error when the archive file could not be written.
+    pub fn record(&self, key: &str) -> io::Result<()> {
+        // Hold the lock while writing so the lines of the workers do not end up mixed in the file.
+        let mut keys = self.keys.lock().expect("Archive lock poisoned");
+        if keys.contains(key) {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{key}")?;
+        keys.insert(key.to_string());
+        Ok(())
+    }
+}
+
+/// The archive key of a url, known before the download for the urls that carry their video id.
+/// # Parameters
+/// url - The url to download
+/// # Returns
+/// The `<extractor> <video id>` key, None when it can only be known after the download.
+pub fn archive_key_for_url(url: &str) -> Option<String> {
+    let parsed = Url::parse(url).ok()?;
+    youtube_video_id(&parsed).map(|id| format!("youtube {id}"))
+}
@@ -0,0 +1,524 @@
+use crate::default_jobs;
+use crate::downloader::YT_DLP_DEFAULT_ARGS;
+use crate::merge::ConflictPolicy;
+use crate::workdir::CollisionPolicy;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use toml::{Table, Value};
+
+/// Name of the profile used when `--profile` is not passed, it holds the flags yt-parallel always used.
+pub const DEFAULT_PROFILE: &str = "default";
+/// The name of the download folder when it is not configured, the date of the batch.
+pub const DEFAULT_FOLDER_NAME: &str = "%Y%m%d";
+/// Name of the config file in the folder yt-parallel is started from, for settings that belong to a project.
+pub const PROJECT_CONFIG_FILE: &str = "yt-parallel.toml";
+/// Environment variables start with this, followed by the key in upper case like `YT_PARALLEL_MOVE_TARGET`.
+pub const ENV_PREFIX: &str = "YT_PARALLEL_";
+/// The name of the layer of the built-in defaults
+const DEFAULT_SOURCE: &str = "default";
+/// The comments of `config show` line up up to this column, the longer lines just get theirs after them
+const SHOW_COMMENT_COLUMN: usize = 48;
+
+/// The configuration, all keys are optional so an empty or missing file gives the defaults. It is put together from
+/// layers, a later layer wins per key: the built-in defaults, the system config file, the user config file, the
+/// project config file, the `--config` file, the environment variables and the commandline.
+/// ```toml
+/// move-target = "/mnt/nas/media/youtube/"
+/// work-dir = "/scratch/yt-parallel"
+/// folder-name = "%Y%m%d-{list_name}"
+/// collision = "merge"
+/// conflict = "keep-both"
+/// download-tool = "yt-dlp"
+/// jobs = 4
+/// profile = "default"
+///
+/// [profiles.audio-only]
+/// args = ["--extract-audio", "--audio-format", "mp3"]
+///
+/// [gallery-dl]
+/// command = "gallery-dl"
+/// hosts = ["imgur.com", "flickr.com"]
+///
+/// [tools.podcast]
+/// command = "/usr/local/bin/podcast-fetch"
+/// args = ["--out", "{output_dir}", "{url}"]
+/// hosts = ["podcasts.example.com"]
+///
+/// [log]
+/// file = "/var/log/yt-parallel/yt-parallel.log"
+/// level = "debug"
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// The folder the downloads are moved to when the batch is done, they stay where they are when not set
+    pub move_target: Option<String>,
+    /// The folder the download folder is made in, the folder yt-parallel is started from when not set
+    pub work_dir: Option<String>,
+    /// The name of the download folder, strftime patterns and the tokens `{list_name}`, `{run_id}` and `{hostname}`
+    /// are filled in
+    pub folder_name: String,
+    /// What to do when the download folder or the folder at the move target already exists
+    pub collision: CollisionPolicy,
+    /// What to do with a file that is already in the folder at the move target when merging into it
+    pub conflict: ConflictPolicy,
+    /// The download tool to run, a name that can be found in PATH or a full path
+    pub download_tool: String,
+    /// The number of downloads to run in parallel
+    pub jobs: usize,
+    /// The yt-dlp argument profile to download with
+    pub profile: String,
+    pub gallery_dl: GalleryDlConfig,
+    pub tools: BTreeMap<String, ToolConfig>,
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    pub log: LogConfig,
+    /// The config files that were read, the lowest layer first
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+    /// The layer every key that was set came from, by its dotted name like `log.level`
+    #[serde(skip)]
+    pub origins: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            move_target: None,
+            work_dir: None,
+            folder_name: String::from(DEFAULT_FOLDER_NAME),
+            collision: CollisionPolicy::Suffix,
+            conflict: ConflictPolicy::Skip,
+            download_tool: String::from("yt-dlp"),
+            jobs: default_jobs(),
+            profile: String::from(DEFAULT_PROFILE),
+            gallery_dl: GalleryDlConfig::default(),
+            tools: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+            log: LogConfig::default(),
+            files: Vec::new(),
+            origins: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// The yt-dlp arguments of a profile, a profile in the config file wins over the built-in default profile.
+    /// # Parameters
+    /// name - The name of the profile
+    /// # Returns
+    /// The arguments that go in front of the url, None when there is no such profile.
+    pub fn profile_args(&self, name: &str) -> Option<Vec<String>> {
+        match self.profiles.get(name) {
+            Some(profile) => Some(profile.args.clone()),
+            None if name == DEFAULT_PROFILE => {
+                Some(YT_DLP_DEFAULT_ARGS.iter().map(|a| a.to_string()).collect())
+            }
+            None => None,
+        }
+    }
+
+    /// The names of all the profiles that can be selected, the built-in ones included.
+    pub fn profile_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.profiles.keys().cloned().collect();
+        if !self.profiles.contains_key(DEFAULT_PROFILE) {
+            names.insert(0, DEFAULT_PROFILE.to_string());
+        }
+        names
+    }
+}
+
+/// A named set of yt-dlp arguments, they replace the default flags.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub args: Vec<String>,
+}
+
+/// Settings for the log file
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LogConfig {
+    /// The log file, no log file when not set
+    pub file: Option<String>,
+    /// The level of the log file, trace, debug, info, warn or error
+    pub level: Option<String>,
+    /// When to rotate the log file, daily, never or a size like 10MiB
+    pub rotate: Option<String>,
+    /// The number of rotated log files to keep
+    pub keep: Option<usize>,
+}
+
+/// Settings for the gallery-dl backend, it is used for the image posts on the hosts listed.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct GalleryDlConfig {
+    pub command: String,
+    pub hosts: Vec<String>,
+}
+
+impl Default for GalleryDlConfig {
+    fn default() -> Self {
+        GalleryDlConfig {
+            command: String::from("gallery-dl"),
+            hosts: vec![
+                String::from("imgur.com"),
+                String::from("flickr.com"),
+                String::from("deviantart.com"),
+                String::from("pixiv.net"),
+                String::from("artstation.com"),
+            ],
+        }
+    }
+}
+
+/// A generic download tool, the args are a template where `{url}` and `{output_dir}` are replaced per download.
+/// When the args have no `{url}` the url is appended as the last argument.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ToolConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Exit codes that count as a successful download
+    #[serde(default = "default_success_codes")]
+    pub success_codes: Vec<i32>,
+    /// Lines of the tool output starting with this prefix name a downloaded file
+    #[serde(default)]
+    pub output_prefix: Option<String>,
+}
+
+fn default_success_codes() -> Vec<i32> {
+    vec![0]
+}
+
+/// A layer of the configuration, the keys it sets and where they came from.
+#[derive(Debug, Clone)]
+pub struct ConfigLayer {
+    /// Where the layer came from, like the path of the file or the environment variable
+    pub source: String,
+    pub values: Table,
+}
+
+impl ConfigLayer {
+    /// An empty layer, the keys are added with `set`
+    pub fn new(source: &str) -> ConfigLayer {
+        ConfigLayer {
+            source: source.to_string(),
+            values: Table::new(),
+        }
+    }
+
+    /// Parse a config file into a layer, the file has to be a valid config on its own.
+    /// # Parameters
+    /// source - Where the text came from, used in the error message<br/>
+    /// content - The toml text<br/>
+    /// # Returns
+    /// The layer or the error message that explains why the text is not a valid config.
+    pub fn parse(source: &str, content: &str) -> Result<ConfigLayer, String> {
+        parse_config(content).map_err(|e| format!("Could not parse {source}: {e}"))?;
+        let values: Table = content
+            .parse()
+            .map_err(|e| format!("Could not parse {source}: {e}"))?;
+        Ok(ConfigLayer {
+            source: source.to_string(),
+            values,
+        })
+    }
+
+    /// Set a key in the layer, the sections in a dotted key like `log.level` are created when needed.
+    pub fn set(&mut self, key: &str, value: impl Into<Value>) {
+        let mut table = &mut self.values;
+        let mut parts: Vec<&str> = key.split('.').collect();
+        let last = parts.pop().expect("A key has at least one part");
+        for part in parts {
+            table = table
+                .entry(part)
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+                .expect("A section of a key should be a table");
+        }
+        table.insert(last.to_string(), value.into());
+    }
+}
+
+/// The location of the system wide config file, `/etc/yt-parallel/config.toml` or `%PROGRAMDATA%` on windows.
+/// # Returns
+/// The path of the system config file, None when the OS has no such place.
+pub fn system_config_path() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("PROGRAMDATA")
+            .map(|data| Path::new(&data).join("yt-parallel").join("config.toml"))
+    } else {
+        Some(PathBuf::from("/etc/yt-parallel/config.toml"))
+    }
+}
+
+/// The location of the user config file.
+/// This is `$XDG_CONFIG_HOME/yt-parallel/config.toml`, falling back to `~/.config` and `%APPDATA%` on windows.
+/// # Returns
+/// The path of the user config file, None when no home or config folder is known.
+pub fn default_config_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(base.join("yt-parallel").join("config.toml"))
+}
+
+/// Read a config file into a layer.
+/// # Parameters
+/// path - The config file<br/>
+/// required - Whether it is an error when the file is not there<br/>
+/// # Returns
+/// The layer, None when an optional file is not there, or the error message when the file could not be used.
+fn file_layer(path: &Path, required: bool) -> Result<Option<ConfigLayer>, String> {
+    if !path.exists() && !required {
+        debug!("No config file at {}", path.display());
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    ConfigLayer::parse(&path.display().to_string(), &content).map(Some)
+}
+
+/// The layers of the environment variables, a layer per variable so each value can tell which one it came from. Every
+/// config key has a variable, `jobs` is `YT_PARALLEL_JOBS` and `log.level` in the `[log]` section is
+/// `YT_PARALLEL_LOG_LEVEL`. The value is read as toml, so numbers and lists like `["imgur.com"]` work, and as text
+/// when it is not valid as toml for the key.
+/// # Parameters
+/// vars - The environment variables<br/>
+/// skip - The variables that are read with the commandline options<br/>
+/// # Returns
+/// The layers in the order of the names, or the error message of the first variable that is not a valid setting.
+pub fn env_layers(
+    vars: impl IntoIterator<Item = (String, String)>,
+    skip: &[String],
+) -> Result<Vec<ConfigLayer>, String> {
+    let vars: BTreeMap<String, String> = vars
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX) && !skip.contains(name))
+        .collect();
+    let sections: Vec<String> = Table::try_from(Config::default())
+        .map_err(|e| format!("Could not use the defaults: {e}"))?
+        .into_iter()
+        .filter(|(_, value)| value.is_table())
+        .map(|(section, _)| section)
+        .collect();
+    let mut layers = Vec::new();
+    for (name, value) in vars {
+        let key = env_key(&name[ENV_PREFIX.len()..], &sections);
+        let source = format!("environment {name}");
+        let mut layer = ConfigLayer::new(&source);
+        match value.parse::<Value>() {
+            Ok(parsed) => layer.set(&key, parsed),
+            Err(_) => layer.set(&key, value.as_str()),
+        }
+        if merge_layers(std::slice::from_ref(&layer)).is_err() {
+            layer = ConfigLayer::new(&source);
+            layer.set(&key, value.as_str());
+        }
+        merge_layers(std::slice::from_ref(&layer))
+            .map_err(|e| format!("{name} is not a valid setting for {key}. {e}"))?;
+        layers.push(layer);
+    }
+    Ok(layers)
+}
+
+/// The config key of an environment variable without its prefix, `LOG_LEVEL` is `log.level` as there is a `[log]`
+/// section and `MOVE_TARGET` is `move-target`.
+fn env_key(name: &str, sections: &[String]) -> String {
+    let key = name.to_lowercase().replace('_', "-");
+    for section in sections {
+        if let Some(rest) = key.strip_prefix(&format!("{section}-")) {
+            return format!("{section}.{rest}");
+        }
+    }
+    key
+}
+
+/// Load the configuration from all its layers, a later layer wins per key. The system, user and project config files
+/// are optional, a config file passed explicitly must exist.
+/// # Parameters
+/// path - The config file passed on the commandline, if any<br/>
+/// commandline - The keys set with the commandline options, the top layers<br/>
+/// skip_env - The environment variables that are read with the commandline options<br/>
+/// # Returns
+/// The merged configuration or the error message that explains why a layer could not be used.
+pub fn load_config(
+    path: Option<&str>,
+    commandline: Vec<ConfigLayer>,
+    skip_env: &[String],
+) -> Result<Config, String> {
+    let mut layers = Vec::new();
+    let files = [
+        (system_config_path(), false),
+        (default_config_path(), false),
+        (
+            env::current_dir()
+                .ok()
+                .map(|dir| dir.join(PROJECT_CONFIG_FILE)),
+            false,
+        ),
+        (path.map(PathBuf::from), true),
+    ];
+    let mut read = Vec::new();
+    for (file, required) in files {
+        if let Some(file) = file
+            && let Some(layer) = file_layer(&file, required)?
+        {
+            read.push(file);
+            layers.push(layer);
+        }
+    }
+    // A variable that is not unicode cannot be a setting of ours, it is left out rather than failing on it.
+    let vars = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+    layers.extend(env_layers(vars, skip_env)?);
+    layers.extend(commandline);
+    let mut config = merge_layers(&layers)?;
+    config.files = read;
+    Ok(config)
+}
+
+/// Merge layers on top of the built-in defaults, the sections are merged key by key and a later layer wins.
+/// # Parameters
+/// layers - The layers, the lowest first<br/>
+/// # Returns
+/// The configuration with the origin of every key, or the error message when the merged keys are not a valid config.
+pub fn merge_layers(layers: &[ConfigLayer]) -> Result<Config, String> {
+    let mut merged = Table::new();
+    let mut origins = BTreeMap::new();
+    let defaults = Table::try_from(Config::default())
+        .map_err(|e| format!("Could not use the defaults: {e}"))?;
+    merge_table(&mut merged, &defaults, "", DEFAULT_SOURCE, &mut origins);
+    for layer in layers {
+        merge_table(&mut merged, &layer.values, "", &layer.source, &mut origins);
+    }
+    let mut config: Config = merged
+        .try_into()
+        .map_err(|e| format!("The configuration is not valid: {}", e.message().trim()))?;
+    config.origins = origins;
+    Ok(config)
+}
+
+/// Merge the keys of a layer into the merged table, the values that are not a section replace what was there.
+fn merge_table(
+    merged: &mut Table,
+    layer: &Table,
+    prefix: &str,
+    source: &str,
+    origins: &mut BTreeMap<String, String>,
+) {
+    for (key, value) in layer {
+        let dotted = format!("{prefix}{key}");
+        match value {
+            Value::Table(section) => {
+                if !merged.get(key).is_some_and(Value::is_table) {
+                    origins.remove(&dotted);
+                    merged.insert(key.clone(), Value::Table(Table::new()));
+                }
+                let Some(Value::Table(merged_section)) = merged.get_mut(key) else {
+                    unreachable!("The section was just made a table");
+                };
+                merge_table(
+                    merged_section,
+                    section,
+                    &format!("{dotted}."),
+                    source,
+                    origins,
+                );
+            }
+            value => {
+                // A value replacing a section takes the origins of the keys in the section with it.
+                origins.retain(|k, _| !k.starts_with(&format!("{dotted}.")));
+                origins.insert(dotted, source.to_string());
+                merged.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Render the effective configuration as toml, with the layer every value came from as a comment after it.
+/// # Parameters
+/// config - The merged configuration
+/// # Returns
+/// The text to print for `config show`.
+pub fn render_config(config: &Config) -> String {
+    let mut rendered = String::from("# The effective configuration, later layers win:\n");
+    let _ = writeln!(rendered, "#   {DEFAULT_SOURCE}");
+    for file in &config.files {
+        let _ = writeln!(rendered, "#   {}", file.display());
+    }
+    let _ = writeln!(rendered, "#   environment ({ENV_PREFIX}*)\n#   commandline");
+    let mut lines = Vec::new();
+    match Table::try_from(config) {
+        Ok(values) => flatten_table(&values, &[], &mut lines),
+        Err(e) => {
+            let _ = writeln!(rendered, "# Could not render the configuration: {e}");
+        }
+    }
+    let width = lines
+        .iter()
+        .map(|(line, _)| line.len())
+        .filter(|length| *length <= SHOW_COMMENT_COLUMN)
+        .max()
+        .unwrap_or(0);
+    for (line, key) in lines {
+        let source = config
+            .origins
+            .get(&key)
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_SOURCE);
+        let _ = writeln!(rendered, "{line:width$}  # {source}");
+    }
+    rendered
+}
+
+/// The values of a table as `dotted.key = value` lines with their dotted key, the plain values before the sections.
+fn flatten_table(table: &Table, path: &[&str], lines: &mut Vec<(String, String)>) {
+    for (key, value) in table.iter().filter(|(_, v)| !v.is_table()) {
+        let mut key_path = path.to_vec();
+        key_path.push(key);
+        let rendered: Vec<String> = key_path.iter().map(|k| render_key(k)).collect();
+        lines.push((
+            format!("{} = {}", rendered.join("."), value),
+            key_path.join("."),
+        ));
+    }
+    for (key, value) in table {
+        if let Value::Table(section) = value {
+            let mut key_path = path.to_vec();
+            key_path.push(key);
+            flatten_table(section, &key_path, lines);
+        }
+    }
+}
+
+/// A key as toml writes it, quoted when it is not a bare key
+fn render_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        key.to_string()
+    } else {
+        Value::String(key.to_string()).to_string()
+    }
+}
+
+/// Parse the content of a config file.
+/// # Parameters
+/// content - The toml text
+/// # Returns
+/// The configuration or the toml error.
+pub fn parse_config(content: &str) -> Result<Config, toml::de::Error> {
+    toml::from_str(content)
+}
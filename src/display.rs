@@ -0,0 +1,191 @@
+use crate::progress::{Phase, Progress};
+use crate::render_duration_readable;
+use crate::results::JobResult;
+use chrono::Local;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::info;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{IsTerminal, stdout};
+use std::time::{Duration, Instant};
+
+/// How often a progress line is logged when there are no bars to show
+const PLAIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(30);
+/// The bars of the downloads count in tenths of a percent
+const BAR_LENGTH: u64 = 1000;
+
+/// The bars to draw the progress on, on standard output when that is a terminal and hidden otherwise. The logging
+/// is written around them, so it has to know them before the first line is logged.
+pub fn terminal_bars() -> MultiProgress {
+    let target = if stdout().is_terminal() {
+        ProgressDrawTarget::stdout()
+    } else {
+        ProgressDrawTarget::hidden()
+    };
+    MultiProgress::with_draw_target(target)
+}
+
+/// A download that is running, with the last speed it reported
+struct ActiveDownload {
+    bar: ProgressBar,
+    speed: Option<u64>,
+    eta: Option<Duration>,
+}
+
+/// The progress of the batch, a bar for every running download and one for the batch as a whole. When the bars are
+/// hidden, because standard output is not a terminal, a progress line is logged now and then instead.
+pub struct ProgressDisplay {
+    bars: MultiProgress,
+    batch_bar: ProgressBar,
+    active: HashMap<usize, ActiveDownload>,
+    total: usize,
+    finished: usize,
+    started: Instant,
+    last_plain_line: Instant,
+}
+
+impl ProgressDisplay {
+    /// Start the display of a batch
+    /// # Parameters
+    /// bars - The bars to draw on, from `terminal_bars`<br/>
+    /// total - The number of entries in the batch<br/>
+    pub fn new(bars: &MultiProgress, total: usize) -> ProgressDisplay {
+        let batch_bar = bars.add(ProgressBar::new(total as u64));
+        batch_bar.set_style(
+            ProgressStyle::with_template(
+                "{elapsed_precise} [{wide_bar:.green}] {pos}/{len} done {msg}",
+            )
+            .expect("Batch bar template should be valid")
+            .progress_chars("=> "),
+        );
+        let now = Instant::now();
+        ProgressDisplay {
+            bars: bars.clone(),
+            batch_bar,
+            active: HashMap::new(),
+            total,
+            finished: 0,
+            started: now,
+            last_plain_line: now,
+        }
+    }
+
+    /// Show the progress a download reported, the first report of a download adds its bar.
+    /// # Parameters
+    /// line_number - The line of the entry in the video list<br/>
+    /// url - The url of the entry<br/>
+    /// progress - What the tool reported<br/>
+    pub fn update(&mut self, line_number: usize, url: &str, progress: &Progress) {
+        let bars = &self.bars;
+        let batch_bar = &self.batch_bar;
+        let download = self.active.entry(line_number).or_insert_with(|| {
+            let bar = bars.insert_before(batch_bar, ProgressBar::new(BAR_LENGTH));
+            bar.set_style(
+                ProgressStyle::with_template("{prefix:>5} [{bar:30.cyan}] {msg}")
+                    .expect("Download bar template should be valid")
+                    .progress_chars("=> "),
+            );
+            bar.set_prefix(line_number.to_string());
+            ActiveDownload {
+                bar,
+                speed: None,
+                eta: None,
+            }
+        });
+        if let Some(percent) = progress.percent {
+            download
+                .bar
+                .set_position((percent * BAR_LENGTH as f64 / 100.0) as u64);
+        }
+        // Only a download reports a speed, the phases after it are not moving any bytes.
+        download.speed = match progress.phase {
+            Phase::Downloading => progress.speed,
+            _ => None,
+        };
+        download.eta = progress.eta;
+        download
+            .bar
+            .set_message(format!("{} {}", render_progress(progress), url));
+        self.refresh();
+    }
+
+    /// Take the bar of a finished download away and count it for the batch
+    pub fn finish(&mut self, result: &JobResult) {
+        if let Some(download) = self.active.remove(&result.line_number) {
+            download.bar.finish_and_clear();
+            self.bars.remove(&download.bar);
+        }
+        self.finished += 1;
+        self.batch_bar.inc(1);
+        self.refresh();
+    }
+
+    /// Clear the bars, done when the downloads are over so the summary is not drawn over.
+    pub fn close(&self) {
+        self.batch_bar.finish_and_clear();
+        let _ = self.bars.clear();
+    }
+
+    /// Update the speed and the estimated end time of the batch, or log them when there are no bars.
+    fn refresh(&mut self) {
+        let speed: u64 = self.active.values().filter_map(|d| d.speed).sum();
+        let mut status = format!("{} running at {}/s", self.active.len(), render_size(speed));
+        if let Some(eta) = self.estimate_remaining() {
+            let end = Local::now() + chrono::Duration::from_std(eta).unwrap_or_default();
+            let _ = write!(status, ", done around {}", end.format("%H:%M"));
+        }
+        if self.bars.is_hidden() {
+            if self.last_plain_line.elapsed() >= PLAIN_PROGRESS_INTERVAL {
+                info!(
+                    "Progress: {} of {} done, {}",
+                    self.finished, self.total, status
+                );
+                self.last_plain_line = Instant::now();
+            }
+        } else {
+            self.batch_bar.set_message(status);
+        }
+    }
+
+    /// The time left for the batch, by the time the finished downloads took on average. Until the first one is done
+    /// the longest estimate of the running downloads is the best we have.
+    fn estimate_remaining(&self) -> Option<Duration> {
+        if self.finished > 0 {
+            let per_entry = self.started.elapsed() / self.finished as u32;
+            Some(per_entry * (self.total - self.finished) as u32)
+        } else {
+            self.active.values().filter_map(|d| d.eta).max()
+        }
+    }
+}
+
+/// Render a progress report for the logging, like `Downloading 42.3% of 1.20GiB at 3.10MiB/s ETA 00:00:40`
+pub fn render_progress(progress: &Progress) -> String {
+    let mut rendered = format!("{:?}", progress.phase);
+    if let Some(percent) = progress.percent {
+        let _ = write!(rendered, " {percent:.1}%");
+    }
+    if let Some(total) = progress.total_bytes {
+        let _ = write!(rendered, " of {}", render_size(total));
+    }
+    if let Some(speed) = progress.speed {
+        let _ = write!(rendered, " at {}/s", render_size(speed));
+    }
+    if let Some(eta) = progress.eta {
+        let eta = chrono::Duration::from_std(eta).unwrap_or_default();
+        let _ = write!(rendered, " ETA {}", render_duration_readable(eta));
+    }
+    rendered
+}
+
+/// Render a number of bytes in the binary units yt-dlp uses, like `1.20GiB`
+pub fn render_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return format!("{size:.2}{unit}");
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.2}TiB")
+}
@@ -0,0 +1,337 @@
+use crate::config::{Config, ToolConfig};
+use crate::progress::{Progress, parse_yt_dlp_progress};
+use crate::resolve_downloader;
+use crate::videolist::VideoEntry;
+use log::{debug, info, warn};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use url::Url;
+
+/// The arguments yt-dlp gets for every download, by default we remove the sponsor-blocks as they are repetitive and
+/// most of the time not even relevant.
+pub const YT_DLP_DEFAULT_ARGS: [&str; 9] = [
+    "--sponsorblock-remove",
+    "default",
+    "--retries",
+    "infinite",
+    "--fragment-retries",
+    "infinite",
+    "--buffer-size",
+    "16K",
+    "--no-check-certificate",
+];
+
+/// A download tool, it knows how to call the tool for a url and how to read back what the tool did.
+pub trait Downloader: Send + Sync {
+    /// Name of the backend, used in the logging
+    fn name(&self) -> &str;
+    /// Build the command that downloads the entry into the output folder, the caller handles the in and output.
+    fn build_command(&self, entry: &VideoEntry, output_dir: &Path) -> Command;
+    /// Whether the exit status of the tool means the download succeeded
+    fn interpret_status(&self, status: &ExitStatus) -> bool {
+        status.success()
+    }
+    /// The files the tool reported to have written, taken from its standard output
+    fn parse_output(&self, stdout: &str) -> Vec<String>;
+    /// The `<extractor> <video id>` download archive keys of what the tool downloaded, taken from its standard output
+    fn archive_keys(&self, _stdout: &str) -> Vec<String> {
+        Vec::new()
+    }
+    /// The progress a line of the standard output of the tool reports, None when the tool does not report progress
+    fn parse_progress(&self, _line: &str) -> Option<Progress> {
+        None
+    }
+}
+
+/// yt-dlp, the default backend, called with the arguments of the profile of the entry or the default profile.
+pub struct YtDlp {
+    tool: PathBuf,
+    profiles: BTreeMap<String, Vec<String>>,
+    default_profile: String,
+}
+
+impl YtDlp {
+    pub fn new(
+        tool: PathBuf,
+        profiles: BTreeMap<String, Vec<String>>,
+        default_profile: String,
+    ) -> YtDlp {
+        YtDlp {
+            tool,
+            profiles,
+            default_profile,
+        }
+    }
+}
+
+impl Downloader for YtDlp {
+    fn name(&self) -> &str {
+        "yt-dlp"
+    }
+
+    fn build_command(&self, entry: &VideoEntry, output_dir: &Path) -> Command {
+        let profile = entry.profile.as_ref().unwrap_or(&self.default_profile);
+        let mut command = Command::new(&self.tool);
+        if let Some(args) = self.profiles.get(profile) {
+            command.args(args);
+        }
+        // Without a terminal yt-dlp overwrites the progress line with carriage returns, this puts every update on a
+        // line of its own so it can be followed while the download runs.
+        command.arg("--newline");
+        if let Some(name) = &entry.name {
+            command.arg("--output").arg(name);
+        }
+        command.arg(&entry.url).current_dir(output_dir);
+        command
+    }
+
+    fn parse_output(&self, stdout: &str) -> Vec<String> {
+        // Formats that are merged are deleted by yt-dlp afterwards, so we keep them apart until we know whether
+        // a merge follows for the video.
+        let mut files: Vec<String> = Vec::new();
+        let mut pending: Vec<String> = Vec::new();
+        for line in stdout.lines().map(str::trim) {
+            if let Some(file) = line.strip_prefix("[download] Destination: ") {
+                pending.push(file.to_string());
+            } else if let Some(file) = line
+                .strip_prefix("[download] ")
+                .and_then(|l| l.strip_suffix(" has already been downloaded"))
+            {
+                pending.push(file.to_string());
+            } else if let Some(file) = line.strip_prefix("[Merger] Merging formats into ") {
+                pending.clear();
+                files.push(file.trim_matches('"').to_string());
+            } else if let Some(file) = line.strip_prefix("[ExtractAudio] Destination: ") {
+                pending.clear();
+                files.push(file.to_string());
+            } else if line.starts_with("[download] Downloading item") {
+                files.append(&mut pending);
+            }
+        }
+        files.append(&mut pending);
+        files.dedup();
+        files
+    }
+
+    fn archive_keys(&self, stdout: &str) -> Vec<String> {
+        // The extractors log what they work on as `[youtube] dQw4w9WgXcQ: Downloading webpage`, `[info]` logs the
+        // same id for the formats so it is left out.
+        let mut keys: Vec<String> = Vec::new();
+        for line in stdout.lines().map(str::trim) {
+            let Some((tag, rest)) = line.strip_prefix('[').and_then(|l| l.split_once("] ")) else {
+                continue;
+            };
+            let Some((id, _)) = rest.split_once(": Downloading") else {
+                continue;
+            };
+            if tag == "info" || tag.contains(':') || id.contains(char::is_whitespace) {
+                continue;
+            }
+            let key = format!("{} {}", tag.to_lowercase(), id);
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+
+    fn parse_progress(&self, line: &str) -> Option<Progress> {
+        parse_yt_dlp_progress(line)
+    }
+}
+
+/// gallery-dl for image posts, the files are placed straight in the output folder.
+pub struct GalleryDl {
+    tool: PathBuf,
+}
+
+impl GalleryDl {
+    pub fn new(tool: PathBuf) -> GalleryDl {
+        GalleryDl { tool }
+    }
+}
+
+impl Downloader for GalleryDl {
+    fn name(&self) -> &str {
+        "gallery-dl"
+    }
+
+    fn build_command(&self, entry: &VideoEntry, output_dir: &Path) -> Command {
+        let mut command = Command::new(&self.tool);
+        command.arg("--directory").arg(".");
+        if let Some(name) = &entry.name {
+            command.arg("--filename").arg(name);
+        }
+        command.arg(&entry.url).current_dir(output_dir);
+        command
+    }
+
+    fn parse_output(&self, stdout: &str) -> Vec<String> {
+        // gallery-dl prints every file on its own line, files it skipped as they were already there start with `# `
+        stdout
+            .lines()
+            .map(|line| line.trim_start_matches("# ").trim())
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect()
+    }
+}
+
+/// Any other tool, driven by a `[tools.<name>]` section of the config file.
+pub struct GenericCommand {
+    name: String,
+    tool: PathBuf,
+    config: ToolConfig,
+}
+
+impl GenericCommand {
+    pub fn new(name: String, tool: PathBuf, config: ToolConfig) -> GenericCommand {
+        GenericCommand { name, tool, config }
+    }
+}
+
+impl Downloader for GenericCommand {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn build_command(&self, entry: &VideoEntry, output_dir: &Path) -> Command {
+        // The tool runs inside the output folder, so a relative path would point to a folder inside it again.
+        let output_dir_text = std::path::absolute(output_dir)
+            .unwrap_or_else(|_| output_dir.to_path_buf())
+            .display()
+            .to_string();
+        let name = entry.name.clone().unwrap_or_default();
+        let mut command = Command::new(&self.tool);
+        for arg in &self.config.args {
+            command.arg(
+                arg.replace("{url}", &entry.url)
+                    .replace("{output_dir}", &output_dir_text)
+                    .replace("{name}", &name),
+            );
+        }
+        if !self.config.args.iter().any(|arg| arg.contains("{url}")) {
+            command.arg(&entry.url);
+        }
+        command.current_dir(output_dir);
+        command
+    }
+
+    fn interpret_status(&self, status: &ExitStatus) -> bool {
+        status
+            .code()
+            .is_some_and(|code| self.config.success_codes.contains(&code))
+    }
+
+    fn parse_output(&self, stdout: &str) -> Vec<String> {
+        match &self.config.output_prefix {
+            Some(prefix) => stdout
+                .lines()
+                .filter_map(|line| line.strip_prefix(prefix.as_str()))
+                .map(|file| file.trim().to_string())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// All the backends for a batch, picks the one to use per url.
+pub struct Downloaders {
+    default: Box<dyn Downloader>,
+    profiles: Vec<String>,
+    by_host: Vec<(Vec<String>, Box<dyn Downloader>)>,
+}
+
+impl Downloaders {
+    /// Set up the backends, the configured tools first so they win over gallery-dl, yt-dlp takes the rest.
+    /// Tools that cannot be found are left out with a warning, the urls for them will go to the default tool.
+    /// # Parameters
+    /// default_tool - The resolved path of the `--video-download-tool`<br/>
+    /// config - The loaded configuration<br/>
+    /// default_profile - The yt-dlp profile for the entries that do not name one<br/>
+    /// # Returns
+    /// The backends ready to use.
+    pub fn from_config(
+        default_tool: PathBuf,
+        config: &Config,
+        default_profile: &str,
+    ) -> Downloaders {
+        let mut by_host: Vec<(Vec<String>, Box<dyn Downloader>)> = Vec::new();
+        for (name, tool_config) in &config.tools {
+            match resolve_downloader(&tool_config.command) {
+                Some(tool) => {
+                    info!("Tool {} uses {}", name, tool.display());
+                    by_host.push((
+                        tool_config.hosts.clone(),
+                        Box::new(GenericCommand::new(name.clone(), tool, tool_config.clone())),
+                    ));
+                }
+                None => warn!(
+                    "Tool {} is configured but {} is not present, skipping it",
+                    name, tool_config.command
+                ),
+            }
+        }
+        match resolve_downloader(&config.gallery_dl.command) {
+            Some(tool) => by_host.push((
+                config.gallery_dl.hosts.clone(),
+                Box::new(GalleryDl::new(tool)),
+            )),
+            None => debug!(
+                "{} is not present, image posts go to the default tool",
+                config.gallery_dl.command
+            ),
+        }
+        Downloaders {
+            default: Box::new(YtDlp::new(
+                default_tool,
+                config
+                    .profile_names()
+                    .into_iter()
+                    .filter_map(|name| config.profile_args(&name).map(|args| (name, args)))
+                    .collect(),
+                default_profile.to_string(),
+            )),
+            profiles: config.profile_names(),
+            by_host,
+        }
+    }
+
+    /// Whether the yt-dlp profile can be used for an entry
+    pub fn knows_profile(&self, name: &str) -> bool {
+        self.profiles.iter().any(|profile| profile == name)
+    }
+
+    /// The backend that handles the url
+    /// # Parameters
+    /// url - The url to download
+    /// # Returns
+    /// The first backend that lists the host of the url, or the default backend.
+    pub fn for_url(&self, url: &str) -> &dyn Downloader {
+        self.by_host
+            .iter()
+            .find(|(hosts, _)| host_matches(url, hosts))
+            .map(|(_, downloader)| downloader.as_ref())
+            .unwrap_or(self.default.as_ref())
+    }
+}
+
+/// Check whether the host of the url is one of the hosts or a subdomain of it.
+/// # Parameters
+/// url - The url to check<br/>
+/// hosts - The hosts to check against, like `imgur.com`<br/>
+/// # Returns
+/// True when the url is on one of the hosts, false otherwise or when the url cannot be parsed.
+pub fn host_matches(url: &str, hosts: &[String]) -> bool {
+    let Some(host) = Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_lowercase))
+    else {
+        return false;
+    };
+    hosts.iter().any(|h| {
+        let h = h.to_lowercase();
+        host == h || host.ends_with(&format!(".{h}"))
+    })
+}
@@ -0,0 +1,74 @@
+use crate::videolist::VideoEntry;
+use chrono::Local;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Name of the folder in the work dir that holds the output of the tools, a folder per run with a file per download.
+pub const JOB_LOG_FOLDER: &str = "logs";
+
+/// The file the output of the tool for an entry is kept in, `logs/<download folder>-<run id>/<job id>.log` in the work
+/// dir. It is kept outside the download folder, so it is not moved along with the downloads where it could run into
+/// the logs of an earlier batch. The run id keeps it apart from a later batch that gets the same folder name once this
+/// one has been moved.
+/// # Parameters
+/// folder_name - The download folder of the batch<br/>
+/// run_id - The id of the run, the same as in the log<br/>
+/// entry - The entry that is downloaded<br/>
+/// # Returns
+/// The path of the log file.
+pub fn job_log_path(folder_name: &str, run_id: &str, entry: &VideoEntry) -> PathBuf {
+    let folder = Path::new(folder_name);
+    let mut run_folder = folder.file_name().unwrap_or_default().to_os_string();
+    run_folder.push(format!("-{run_id}"));
+    folder
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(JOB_LOG_FOLDER)
+        .join(run_folder)
+        .join(format!("{}.log", entry.job_id()))
+}
+
+/// Add the output of an attempt to the log of the download, the attempts follow each other in the file. A resumed
+/// batch is another run, the output of the earlier runs stays in their own files.
+/// # Parameters
+/// path - The log file of the download<br/>
+/// attempt - The number of the attempt<br/>
+/// command - The command that was run<br/>
+/// outcome - How the tool ended, like its exit status or why it was killed<br/>
+/// stdout - The standard output of the tool, as it was written<br/>
+/// stderr - The standard error of the tool, as it was written<br/>
+/// # Returns
+/// Nothing on ok, the error when the file could not be written.
+pub fn append_job_log(
+    path: &Path,
+    attempt: u32,
+    command: &Command,
+    outcome: &str,
+    stdout: &[u8],
+    stderr: &[u8],
+) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(
+        file,
+        "=== Attempt {} at {}: {:?}",
+        attempt,
+        Local::now().format("%Y-%m-%dT%H:%M:%S"),
+        command
+    )?;
+    // The tools do not always write valid UTF-8, the odd bytes are replaced rather than failing on them.
+    for (name, output) in [("stdout", stdout), ("stderr", stderr)] {
+        writeln!(file, "--- {name} ---")?;
+        let text = String::from_utf8_lossy(output);
+        file.write_all(text.as_bytes())?;
+        if !text.is_empty() && !text.ends_with('\n') {
+            writeln!(file)?;
+        }
+    }
+    writeln!(file, "--- {outcome} ---")?;
+    Ok(())
+}
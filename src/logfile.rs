@@ -0,0 +1,129 @@
+use crate::progress::parse_size;
+use chrono::{DateTime, Local, NaiveDate};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// When the log file is put aside for a new one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rotation {
+    Never,
+    /// On the first line of a new day
+    Daily,
+    /// When the file would grow past this number of bytes
+    Size(u64),
+}
+
+/// Parse a rotation from the commandline or the config file
+/// # Parameters
+/// rotation - `daily`, `never` or a size like `10MiB`
+/// # Returns
+/// The rotation, or an error message when it is none of those.
+pub fn parse_rotation(rotation: &str) -> Result<Rotation, String> {
+    match rotation {
+        "never" => Ok(Rotation::Never),
+        "daily" => Ok(Rotation::Daily),
+        size => match parse_size(size) {
+            Some(bytes) if bytes > 0 => Ok(Rotation::Size(bytes)),
+            _ => Err(format!(
+                "'{rotation}' is not a valid log rotation, use daily, never or a size like 10MiB"
+            )),
+        },
+    }
+}
+
+/// A log file that rotates, the old files are kept next to it as `<name>.1` (the newest) up to `<name>.<keep>`.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    keep: usize,
+    file: File,
+    size: u64,
+    /// The day the lines in the file are from, for the daily rotation
+    day: NaiveDate,
+}
+
+impl RotatingFile {
+    /// Open the log file to add to it, the folder it is in is created when needed.
+    /// # Parameters
+    /// path - The log file<br/>
+    /// rotation - When to rotate the file<br/>
+    /// keep - How many rotated files to keep, 0 empties the file on rotation<br/>
+    /// # Returns
+    /// The log file, or the error when it cannot be opened.
+    pub fn open(path: &Path, rotation: Rotation, keep: usize) -> io::Result<RotatingFile> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        // A file left by an earlier run belongs to the day it was last written.
+        let day = metadata
+            .modified()
+            .map(|modified| DateTime::<Local>::from(modified).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive());
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            rotation,
+            keep,
+            file,
+            size: metadata.len(),
+            day,
+        })
+    }
+
+    /// The name of a rotated file, 1 is the newest
+    fn rotated(&self, number: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{number}"));
+        PathBuf::from(name)
+    }
+
+    fn needs_rotation(&self, incoming: usize) -> bool {
+        match self.rotation {
+            Rotation::Never => false,
+            Rotation::Daily => Local::now().date_naive() != self.day,
+            Rotation::Size(max) => self.size > 0 && self.size + incoming as u64 > max,
+        }
+    }
+
+    /// Shift the rotated files one up, dropping the oldest, and start a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep > 0 {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for number in (1..self.keep).rev() {
+                let from = self.rotated(number);
+                if from.exists() {
+                    fs::rename(&from, self.rotated(number + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.day = Local::now().date_naive();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The logger writes a line at a time, so rotating here never splits a line over two files. A failed
+        // rotation is not worth losing the line for, it is written to the current file.
+        if self.needs_rotation(buf.len()) && self.rotate().is_err() {
+            self.size = 0;
+            self.day = Local::now().date_naive();
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -1,24 +1,194 @@
+use crate::logfile::{RotatingFile, Rotation};
 use chrono::Local;
-use env_logger::Builder;
-use log::{LevelFilter, trace};
+use env_logger::{Builder, Logger, Target, WriteStyle};
+use indicatif::MultiProgress;
+use log::{LevelFilter, Log, Metadata, Record, trace};
+use serde_json::{Map, Value, json};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::OnceLock;
 use std::{io::Write, str::FromStr};
 use uuid::Uuid;
 
-pub fn initialize_logging(log_level: String) {
-    let request_id = Uuid::new_v4();
+/// The id of this run, in every log line and available to name the download folder after.
+static RUN_ID: OnceLock<Uuid> = OnceLock::new();
+
+/// The id of this run of yt-parallel, it is made on the first call and the same after that.
+pub fn run_id() -> Uuid {
+    *RUN_ID.get_or_init(Uuid::new_v4)
+}
+
+/// How the log lines are written
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// `timestamp [LEVEL] 'run id' - message`, for people
+    Text,
+    /// One JSON object per line, for log collectors like Loki or ELK
+    Json,
+}
+
+/// Where and how to write the log file
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFileSettings {
+    pub path: PathBuf,
+    /// The file has its own level, it can be more chatty than the console
+    pub level: LevelFilter,
+    pub rotation: Rotation,
+    /// The number of rotated files to keep
+    pub keep: usize,
+}
+
+/// The download a worker thread is busy with, it is added to the JSON log lines of that thread.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobContext {
+    pub url: String,
+    pub job_id: String,
+    pub attempt: u32,
+}
+
+thread_local! {
+    static JOB_CONTEXT: RefCell<Option<JobContext>> = const { RefCell::new(None) };
+}
+
+/// Set the download the current thread is busy with, None when it is done with it.
+pub fn set_job_context(context: Option<JobContext>) {
+    JOB_CONTEXT.with(|current| *current.borrow_mut() = context);
+}
+
+/// Set the attempt of the download the current thread is busy with
+pub fn set_job_attempt(attempt: u32) {
+    JOB_CONTEXT.with(|current| {
+        if let Some(context) = current.borrow_mut().as_mut() {
+            context.attempt = attempt;
+        }
+    });
+}
+
+/// The download the current thread is busy with
+pub fn job_context() -> Option<JobContext> {
+    JOB_CONTEXT.with(|current| current.borrow().clone())
+}
+
+/// Render a log record as a JSON line
+/// # Parameters
+/// record - The record to render<br/>
+/// run_id - The id of this run of yt-parallel<br/>
+/// # Returns
+/// The JSON object, the job fields are only there when the record was logged while working on a download.
+pub fn json_log_line(record: &Record, run_id: &Uuid) -> String {
+    let mut line = Map::new();
+    line.insert("timestamp".into(), json!(Local::now().to_rfc3339()));
+    line.insert("level".into(), json!(record.level().as_str()));
+    line.insert("run_id".into(), json!(run_id.to_string()));
+    line.insert("module".into(), json!(record.module_path()));
+    line.insert("message".into(), json!(record.args().to_string()));
+    if let Some(context) = job_context() {
+        line.insert("url".into(), json!(context.url));
+        line.insert("job_id".into(), json!(context.job_id));
+        line.insert("attempt".into(), json!(context.attempt));
+    }
+    Value::Object(line).to_string()
+}
+
+/// Passes the log lines to the console with the progress bars out of the way, so a line does not end up in the
+/// middle of a bar and the bars are drawn again below it. The lines also go to the log file, when there is one.
+struct BarsAwareLogger {
+    logger: Logger,
+    file_logger: Option<Logger>,
+    bars: MultiProgress,
+}
+
+impl Log for BarsAwareLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.logger.enabled(metadata)
+            || self
+                .file_logger
+                .as_ref()
+                .is_some_and(|file_logger| file_logger.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if self.logger.matches(record) {
+            self.bars.suspend(|| self.logger.log(record));
+        }
+        if let Some(file_logger) = &self.file_logger {
+            file_logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.logger.flush();
+        if let Some(file_logger) = &self.file_logger {
+            file_logger.flush();
+        }
+    }
+}
+
+/// Set up the logging to the console and, when asked, to a log file.
+/// # Parameters
+/// log_level - The level of the console<br/>
+/// format - How to write the lines, the same for the console and the file<br/>
+/// bars - The progress bars the console lines are written around<br/>
+/// log_file - The log file, if any<br/>
+/// # Returns
+/// Nothing on ok, the error when the log file cannot be opened, the console logging is set up then anyway.
+pub fn initialize_logging(
+    log_level: String,
+    format: LogFormat,
+    bars: &MultiProgress,
+    log_file: Option<&LogFileSettings>,
+) -> std::io::Result<()> {
+    let request_id = run_id();
     let level = LevelFilter::from_str(log_level.as_str()).unwrap_or(LevelFilter::Trace);
-    Builder::new()
-        .format(move |buf, record| {
-            writeln!(
-                buf,
-                "{} [{}] '{}' - {}",
-                Local::now().format("%Y-%m-%dT%H:%M:%S"),
-                record.level(),
-                &request_id,
-                record.args()
-            )
-        })
-        .filter(None, level)
-        .init();
-    trace!("Logging system set up.")
+    let logger = log_builder(format, request_id).filter(None, level).build();
+    let (file_logger, file_error) = match log_file {
+        Some(settings) => {
+            match RotatingFile::open(&settings.path, settings.rotation, settings.keep) {
+                Ok(file) => {
+                    let file_logger = log_builder(format, request_id)
+                        .filter(None, settings.level)
+                        .write_style(WriteStyle::Never)
+                        .target(Target::Pipe(Box::new(file)))
+                        .build();
+                    (Some(file_logger), None)
+                }
+                Err(e) => (None, Some(e)),
+            }
+        }
+        None => (None, None),
+    };
+    let max_level = file_logger
+        .as_ref()
+        .map_or(level, |file_logger| file_logger.filter().max(level));
+    let logger = BarsAwareLogger {
+        logger,
+        file_logger,
+        bars: bars.clone(),
+    };
+    // Only the first call sets the logger, the tests call this more than once.
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+    trace!("Logging system set up.");
+    match file_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// A logger builder with the format of the lines
+fn log_builder(format: LogFormat, request_id: Uuid) -> Builder {
+    let mut builder = Builder::new();
+    builder.format(move |buf, record| match format {
+        LogFormat::Text => writeln!(
+            buf,
+            "{} [{}] '{}' - {}",
+            Local::now().format("%Y-%m-%dT%H:%M:%S"),
+            record.level(),
+            &request_id,
+            record.args()
+        ),
+        LogFormat::Json => writeln!(buf, "{}", json_log_line(record, &request_id)),
+    });
+    builder
 }
@@ -1,17 +1,64 @@
+mod archive;
+mod config;
+mod display;
+mod downloader;
+mod joblog;
+mod logfile;
 mod logging;
+mod merge;
+mod progress;
+mod report;
+mod results;
+mod retry;
+mod runner;
+mod shutdown;
+mod state;
 mod tests;
+mod videolist;
+mod workdir;
 
-use crate::logging::initialize_logging;
-use chrono::{Duration, Local};
-use clap::Parser;
+use crate::archive::{Archive, archive_key_for_url};
+use crate::config::{ConfigLayer, LogConfig, load_config, merge_layers, render_config};
+use crate::display::{ProgressDisplay, render_progress, terminal_bars};
+use crate::downloader::{Downloader, Downloaders};
+use crate::joblog::{append_job_log, job_log_path};
+use crate::logfile::parse_rotation;
+use crate::logging::{
+    JobContext, LogFileSettings, LogFormat, initialize_logging, run_id, set_job_attempt,
+    set_job_context,
+};
+use crate::merge::{ConflictPolicy, MergeAction, MergeDecision, merge_folder};
+use crate::report::{MoveOutcome, RunReport};
+use crate::results::{JobResult, WorkerMessage, render_summary, stderr_tail};
+use crate::retry::{RetryPolicy, classify_failure};
+use crate::runner::{KillReason, TimeLimits, run_tool};
+use crate::shutdown::{INTERRUPTED_EXIT_CODE, Shutdown};
+use crate::state::BatchState;
+use crate::videolist::{
+    RejectedLine, VideoEntry, dedupe_entries, read_video_list, write_video_list,
+};
+use crate::workdir::{
+    CollisionPolicy, FolderTokens, create_download_folder, hostname, render_folder_name,
+    resumable_folder, suffixed_path,
+};
+use chrono::{DateTime, Duration, Local};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
 use fs_extra::move_items;
-use log::{debug, error, info, trace, warn};
+use indicatif::MultiProgress;
+use log::{LevelFilter, debug, error, info, trace, warn};
+use std::collections::HashMap;
+use std::fmt::Write as _;
 use std::fs::File;
-use std::io::{self, BufRead};
-use std::path::Path;
-use std::process::{Command, Stdio, exit};
-use std::sync::mpsc::sync_channel;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, exit};
+use std::str::FromStr;
+use std::sync::mpsc::{SyncSender, channel, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use std::{env, fs, thread};
+use toml::Value;
 use which::which;
 
 #[derive(clap::ValueEnum, Clone)]
@@ -38,17 +85,164 @@ fn get_string_from_loglevel(val: LogLevel) -> String {
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Location of the videolist.txt file
-    #[arg(short, long, default_value_t = String::from("./videolist.txt"))]
+    #[arg(short, long, env = "YT_PARALLEL_LOCATION_VIDEO_LIST", default_value_t = String::from("./videolist.txt"))]
     location_video_list: String,
-    #[arg(short, long, default_value_t = String::from("yt-dlp"))]
-    video_download_tool: String,
-    #[arg(value_enum, short, long, default_value_t = LogLevel::Info)]
+    /// Download tool to run, either a name that can be found in PATH or a full path to the executable [default:
+    /// yt-dlp]
+    #[arg(short, long, env = "YT_PARALLEL_VIDEO_DOWNLOAD_TOOL")]
+    video_download_tool: Option<String>,
+    #[arg(value_enum, short, long, env = "YT_PARALLEL_DEBUG_LEVEL", default_value_t = LogLevel::Info)]
     debug_level: LogLevel,
-    #[arg(short, long, default_value_t = String::from(""))]
-    move_target: String,
+    /// How to write the log, json writes one object per line with the url, job id and attempt of the download
+    #[arg(value_enum, long, env = "YT_PARALLEL_LOG_FORMAT", default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// File to write the log to as well as the console, [log] file in the config file
+    #[arg(long, env = "YT_PARALLEL_LOG_FILE")]
+    log_file: Option<String>,
+    /// Level of the log file, defaults to the level of the console
+    #[arg(value_enum, long, env = "YT_PARALLEL_LOG_FILE_LEVEL")]
+    log_file_level: Option<LogLevel>,
+    /// When to rotate the log file: daily, never or a size like 10MiB [default: daily]
+    #[arg(long, env = "YT_PARALLEL_LOG_ROTATE")]
+    log_rotate: Option<String>,
+    /// Number of rotated log files to keep [default: 7]
+    #[arg(long, env = "YT_PARALLEL_LOG_KEEP")]
+    log_keep: Option<usize>,
+    /// Folder to move the downloads to when the batch is done, move-target in the config file. The downloads stay where
+    /// they are when it is not set or ''
+    #[arg(short, long, env = "YT_PARALLEL_MOVE_TARGET")]
+    move_target: Option<String>,
+    /// Folder to make the download folder in, like a fast local disk, work-dir in the config file [default: the
+    /// current folder]
+    #[arg(short, long, env = "YT_PARALLEL_WORK_DIR")]
+    work_dir: Option<String>,
+    /// Name of the download folder, strftime patterns like %Y%m%d and the tokens {list_name}, {run_id} and
+    /// {hostname} are filled in, folder-name in the config file [default: %Y%m%d]
+    #[arg(long, env = "YT_PARALLEL_FOLDER_NAME")]
+    folder_name: Option<String>,
+    /// What to do when the download folder or the folder at the move target already exists, collision in the config
+    /// file [default: suffix]
+    #[arg(value_enum, long, env = "YT_PARALLEL_COLLISION")]
+    collision: Option<CollisionPolicy>,
+    /// What to do with a file that is already in the folder the downloads are merged into with --collision merge,
+    /// conflict in the config file [default: skip]
+    #[arg(value_enum, long, env = "YT_PARALLEL_CONFLICT")]
+    conflict: Option<ConflictPolicy>,
+    /// Config file to read on top of the system, user ($XDG_CONFIG_HOME/yt-parallel/config.toml) and project
+    /// (./yt-parallel.toml) config files
+    #[arg(short, long, env = "YT_PARALLEL_CONFIG")]
+    config: Option<String>,
+    /// Name of the yt-dlp argument profile to download with, profiles are defined in the config file [default:
+    /// default]
+    #[arg(short, long, env = "YT_PARALLEL_PROFILE")]
+    profile: Option<String>,
+    /// Download archive file, videos in it are skipped and downloaded videos are added, same format as the yt-dlp
+    /// --download-archive file
+    #[arg(short = 'a', long, env = "YT_PARALLEL_DOWNLOAD_ARCHIVE")]
+    download_archive: Option<String>,
+    /// File to write the urls that failed to, strftime patterns like %Y%m%d are filled in
+    #[arg(short, long, env = "YT_PARALLEL_FAILED_LIST", default_value_t = String::from("failed-%Y%m%d.txt"))]
+    failed_list: String,
+    /// File to write the urls that did not get downloaded to when the batch is stopped with Ctrl-C, strftime
+    /// patterns like %Y%m%d are filled in
+    #[arg(short, long, env = "YT_PARALLEL_UNFINISHED_LIST", default_value_t = String::from("unfinished-%Y%m%d.txt"))]
+    unfinished_list: String,
+    /// Number of times a download is tried before it counts as failed, 1 means no retries
+    #[arg(long, env = "YT_PARALLEL_MAX_ATTEMPTS", default_value_t = 3)]
+    max_attempts: u32,
+    /// Seconds to wait before the first retry of a download, it doubles for every retry after that
+    #[arg(long, env = "YT_PARALLEL_RETRY_DELAY", default_value_t = 15)]
+    retry_delay: u64,
+    /// Minutes a download may take before it is killed, 0 means no limit
+    #[arg(long, env = "YT_PARALLEL_TIMEOUT", default_value_t = 0)]
+    timeout: u64,
+    /// Minutes a download may go without any output before it is killed as stalled, 0 means no limit
+    #[arg(long, env = "YT_PARALLEL_STALL_TIMEOUT", default_value_t = 0)]
+    stall_timeout: u64,
+    /// Resume a batch that was cut off by a crash or reboot, the downloads that finished are skipped and the partial
+    /// ones continue. Without a folder the newest batch of today with a state file is resumed, pass the download folder
+    /// of the batch, in the work dir, for another one
+    #[arg(long, env = "YT_PARALLEL_RESUME", num_args = 0..=1, default_missing_value = "", value_name = "FOLDER")]
+    resume: Option<String>,
+    /// File to write a JSON report of the run to, strftime patterns like %Y%m%d are filled in
+    #[arg(long, env = "YT_PARALLEL_REPORT")]
+    report: Option<String>,
+    /// Number of downloads to run in parallel, defaults to the number of cpus
+    #[arg(short, long, env = "YT_PARALLEL_JOBS")]
+    jobs: Option<usize>,
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Work with the configuration
+    #[command(subcommand)]
+    Config(ConfigCommands),
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Print the effective configuration, with the config file, environment variable or commandline every value came
+    /// from
+    Show,
+}
+
+/// The settings of a batch, they are the same for all the entries in the video list.
+struct BatchSettings {
+    /// The path you want to move the files to when done downloaded, None to leave them where they are
+    move_target: Option<String>,
+    /// What to do when the folder is already at the move target
+    collision: CollisionPolicy,
+    /// What to do with a file that is already there when merging into the folder at the move target
+    conflict: ConflictPolicy,
+    /// The number of workers that download in parallel
+    jobs: usize,
+    /// The backends to download the urls with
+    downloaders: Arc<Downloaders>,
+    /// The download archive, the videos in it are skipped and the downloaded ones are added
+    archive: Option<Arc<Archive>>,
+    /// The file to write the urls that failed to, in the video list format
+    failed_list: PathBuf,
+    /// How often a failed download is tried again
+    retry_policy: RetryPolicy,
+    /// How long a download may run before it is killed
+    time_limits: TimeLimits,
+    /// The file to write the urls that were not downloaded when the batch is stopped, in the video list format
+    unfinished_list: PathBuf,
+    /// Tells whether the user asked to stop
+    shutdown: Shutdown,
+    /// The state of the entries, kept in the download folder to be able to resume the batch
+    state: Arc<BatchState>,
+    /// The terminal to draw the progress of the downloads on
+    progress_bars: MultiProgress,
+}
+
+/// What a worker needs to download the entries it takes off the queue, every worker gets its own copy.
+#[derive(Clone)]
+struct WorkerContext {
+    /// The path of the directory to download to
+    folder_name: String,
+    /// The download archive to add the videos to when they have been downloaded
+    archive: Option<Arc<Archive>>,
+    /// How often and how long apart to try
+    retry_policy: RetryPolicy,
+    /// How long the tool may run per attempt before it is killed
+    time_limits: TimeLimits,
+    /// Kills the tool and stops the retries when the user asks to stop
+    shutdown: Shutdown,
+    /// The channel to the main thread, for the progress and the results
+    messages: SyncSender<WorkerMessage>,
 }
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Room in the channel to the main thread for progress reports on top of the results, when it is full the progress
+/// reports are dropped rather than holding up the downloads.
+const PROGRESS_BUFFER: usize = 256;
+/// The backoff between retries never grows past this
+const MAX_RETRY_DELAY_SECONDS: u64 = 600;
+/// Time the download tool gets to tell its version, a tool that does not know `--version` may take it for a url
+const VERSION_TIMEOUT_SECONDS: u64 = 10;
 
 /// Check downloader present,
 /// Checks whether the yt-dlp app is attainable in the path on the OS.
@@ -57,21 +251,67 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 /// # Returns
 /// True when present, else false. Does not processes errors, it will return false on error.
 pub fn check_downloader_present(command: String) -> bool {
-    let is_present = which(command);
-    match is_present {
-        Ok(_) => true,
-        Err(_) => false,
+    resolve_downloader(&command).is_some()
+}
+
+/// Resolve the download tool to the executable that will actually be run.
+/// A bare name is looked up in the PATH, a path (relative or absolute) is checked as is. Relative paths are made
+/// absolute as the downloads run with the download folder as the working directory.
+/// # Parameters
+/// command - The name or path of the download tool
+/// # Returns
+/// The absolute path of the executable, None when it cannot be found or is not executable.
+pub fn resolve_downloader(command: &str) -> Option<PathBuf> {
+    let resolved = which(command).ok()?;
+    if resolved.is_absolute() {
+        Some(resolved)
+    } else {
+        env::current_dir().ok().map(|cwd| cwd.join(resolved))
     }
 }
 
+/// Ask the download tool for its version, to have it in the log and the report when a download breaks after an
+/// update of the tool.
+/// # Parameters
+/// tool - The resolved path of the download tool<br/>
+/// shutdown - The tool is killed when the user asks to stop<br/>
+/// # Returns
+/// The first line the tool prints for `--version`, None when it does not run, does not tell or takes too long.
+pub fn tool_version(tool: &Path, shutdown: &Shutdown) -> Option<String> {
+    let mut command = Command::new(tool);
+    command.arg("--version");
+    let limits = TimeLimits {
+        timeout: Some(std::time::Duration::from_secs(VERSION_TIMEOUT_SECONDS)),
+        stall_timeout: None,
+    };
+    let output = run_tool(&mut command, &limits, shutdown, &mut |_| {}).ok()?;
+    if !output.status.is_some_and(|status| status.success()) {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(String::from)
+}
+
 /// Moves the downloaded yt videos to the target, usually a NAS or a shared folder.
 /// It will delete *.part files, then move the folder.
 /// # Parameters
 /// source - string of the path pointing the source, the path must exist on the system and accessible for read/write
-/// target - string of the destination, the path must exist on the system and accessible for read/write
+/// target - string of the destination, the path must exist on the system and accessible for read/write<br/>
+/// collision - What to do when the folder is already in the destination<br/>
+/// conflict - What to do with a file that is already there when the folder is merged<br/>
 /// # Returns
-/// True on successful move, else false, it will panic out when a system error occurs.
-fn move_to_nas(source: String, target: String) -> bool {
+/// True when everything was moved, false when the move failed or the merge left files behind in the source, with the
+/// decision for every file when the folder was merged. It will panic out when the source cannot be read for the
+/// partial files.
+fn move_to_nas(
+    source: String,
+    target: String,
+    collision: CollisionPolicy,
+    conflict: ConflictPolicy,
+) -> (bool, Vec<MergeDecision>) {
     debug!(
         "Entered the move_nas function, moving from {} to {}",
         source.clone(),
@@ -90,11 +330,7 @@ fn move_to_nas(source: String, target: String) -> bool {
                     debug!("Target created: {}", target.clone())
                 }
                 Err(e) => {
-                    warn!(
-                        "Could not create {} for reason: {}",
-                        target.clone(),
-                        e.to_string()
-                    )
+                    warn!("Could not create {} for reason: {}", target.clone(), e)
                 }
             }
         }
@@ -103,25 +339,45 @@ fn move_to_nas(source: String, target: String) -> bool {
         prune_partial_files(&source, &source_path);
         debug!("Completed pruning partial files");
         //let source_files = fs::read_dir(source_path);
-        let options = fs_extra::dir::CopyOptions::new();
-        let move_result = move_items(&[source_path], &target_path, &options);
+        let folder = target_path.join(source_path.file_name().unwrap_or_default());
+        let move_result = if folder.exists() {
+            move_into_existing(source_path, &folder, collision, conflict)
+        } else {
+            let options = fs_extra::dir::CopyOptions::new();
+            move_items(&[source_path], target_path, &options)
+                .map(|_| Vec::new())
+                .map_err(|e| e.to_string())
+        };
         match move_result {
-            Ok(_) => {
+            Ok(decisions) => {
+                let left_behind = decisions
+                    .iter()
+                    .filter(|d| matches!(d.action, MergeAction::Skipped | MergeAction::Failed))
+                    .count();
+                if left_behind > 0 {
+                    warn!(
+                        "Move from {} to {} is not complete, {} files were skipped or failed",
+                        source.clone(),
+                        target.clone(),
+                        left_behind
+                    );
+                    return (false, decisions);
+                }
                 info!(
                     "Move complete from {} to {}",
                     source.clone(),
                     target.clone()
                 );
-                true
+                (true, decisions)
             }
             Err(e) => {
                 error!(
                     "Could not move {} from {} because of {}",
                     source.clone(),
                     target.clone(),
-                    e.to_string()
+                    e
                 );
-                false
+                (false, Vec::new())
             }
         }
     } else {
@@ -130,82 +386,369 @@ fn move_to_nas(source: String, target: String) -> bool {
             source_path.exists(),
             target_path.exists()
         );
-        false
+        (false, Vec::new())
     }
 }
 
-/// Remove part files before moving as they would not play anyway
+/// Move the download folder to the move target when a folder with its name is already there.
+/// # Parameters
+/// source - The download folder<br/>
+/// folder - The folder with the same name at the move target<br/>
+/// collision - Whether to move it next to it with a counter, merge it into it or not move it<br/>
+/// conflict - What to do with a file that is already there when merging<br/>
+/// # Returns
+/// The decision for every file when it was merged on ok, the error message when the folder was not or not fully
+/// moved.
+fn move_into_existing(
+    source: &Path,
+    folder: &Path,
+    collision: CollisionPolicy,
+    conflict: ConflictPolicy,
+) -> Result<Vec<MergeDecision>, String> {
+    match collision {
+        CollisionPolicy::Suffix => {
+            let free = (2..)
+                .map(|counter| suffixed_path(folder, counter))
+                .find(|candidate| !candidate.exists())
+                .expect("There is always a free counter");
+            info!(
+                "{} already exists, moving to {} instead",
+                folder.display(),
+                free.display()
+            );
+            let mut options = fs_extra::dir::CopyOptions::new();
+            options.copy_inside = true;
+            fs_extra::dir::move_dir(source, &free, &options)
+                .map(|_| Vec::new())
+                .map_err(|e| e.to_string())
+        }
+        CollisionPolicy::Merge => {
+            info!(
+                "{} already exists, merging into it, files that are there already are handled with {}",
+                folder.display(),
+                conflict.name()
+            );
+            let decisions = merge_folder(source, folder, conflict).map_err(|e| e.to_string())?;
+            let count = |action| decisions.iter().filter(|d| d.action == action).count();
+            info!(
+                "Merged {} files: {} moved, {} overwritten, {} kept both, {} skipped, {} failed",
+                decisions.len(),
+                count(MergeAction::Moved),
+                count(MergeAction::Overwritten),
+                count(MergeAction::KeptBoth),
+                count(MergeAction::Skipped),
+                count(MergeAction::Failed)
+            );
+            if count(MergeAction::Skipped) + count(MergeAction::Failed) > 0 {
+                warn!(
+                    "The skipped and failed files are left in {}, move them yourself if they are needed",
+                    source.display()
+                );
+            }
+            Ok(decisions)
+        }
+        CollisionPolicy::Fail => Err(format!("{} already exists", folder.display())),
+    }
+}
+
+/// Remove part files before moving as they would not play anyway, the subdirs of the entries are pruned as well.
 /// # Parameters
 /// source - String of the file that is processed <br/>
 /// source_path - Path of the folder to iterate through  <br/>
 /// # Returns
 /// Nothing
-fn prune_partial_files(source: &String, source_path: &&Path) {
+fn prune_partial_files(source: &str, source_path: &&Path) {
     let files = fs::read_dir(Path::new(&source_path)).unwrap();
     for x in files {
-        let name = x.unwrap().path().display().to_string();
+        let path = x.unwrap().path();
+        if path.is_dir() {
+            prune_partial_files(source, &path.as_path());
+            continue;
+        }
+        let name = path.display().to_string();
         debug!("Processing file {}", name);
         if name.ends_with("part") {
             warn!(
                 "Found partial file in dir {}, removing file {}",
-                source.clone(),
-                name
+                source, name
             );
-            fs::remove_file(Path::new(&format!("{}", name))).unwrap();
+            fs::remove_file(Path::new(&name)).unwrap();
         }
     }
 }
 
+/// Fill in the strftime patterns like `%Y%m%d` of a text with the date and time.
+/// # Parameters
+/// pattern - The text with the patterns<br/>
+/// datetime - The date and time to fill in<br/>
+/// # Returns
+/// The text with the patterns filled in, or an error message when a pattern is not valid.
+fn format_date_pattern(pattern: &str, datetime: &DateTime<Local>) -> Result<String, String> {
+    // Formatting to a string panics on an invalid pattern, writing it reports the error instead.
+    let mut formatted = String::new();
+    write!(formatted, "{}", datetime.format(pattern))
+        .map_err(|_| format!("'{pattern}' has an invalid date pattern"))?;
+    Ok(formatted)
+}
+
 fn render_duration_readable(duration: Duration) -> String {
     let hours = format!("{:0>2}", duration.num_hours());
     let minutes = format!("{:0>2}", duration.num_minutes() % 60);
     let seconds = format!("{:0>2}", duration.num_seconds() % 60);
-    return format!("{}:{}:{}", hours, minutes, seconds);
+    format!("{}:{}:{}", hours, minutes, seconds)
 }
 
 fn main() -> io::Result<()> {
     // Get arguments commandline
-    let args = Args::parse();
-    initialize_logging(get_string_from_loglevel(args.debug_level));
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // The config is loaded before the logging as it can hold the log file, a config that cannot be used is reported
+    // once the logging is there. Until then the log settings of the commandline are all there is.
+    let config_result = load_config(
+        args.config.as_deref(),
+        commandline_layers(&args, &matches),
+        &argument_env_names(),
+    );
+    if let Some(Commands::Config(ConfigCommands::Show)) = &args.command {
+        match &config_result {
+            Ok(config) => {
+                print!("{}", render_config(config));
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                exit(0x0004);
+            }
+        }
+    }
+    let console_level = get_string_from_loglevel(args.debug_level.clone());
+    let log_config = match &config_result {
+        Ok(config) => config.log.clone(),
+        Err(_) => merge_layers(&commandline_layers(&args, &matches))
+            .map(|config| config.log)
+            .unwrap_or_default(),
+    };
+    let (log_file, log_file_error) = match log_file_settings(&log_config, &console_level) {
+        Ok(log_file) => (log_file, None),
+        Err(e) => (None, Some(e)),
+    };
+    let progress_bars = terminal_bars();
+    if let Err(e) = initialize_logging(
+        console_level,
+        args.log_format,
+        &progress_bars,
+        log_file.as_ref(),
+    ) {
+        error!(
+            "Could not open the log file {}: {}, not possible to continue",
+            log_file
+                .as_ref()
+                .map(|f| f.path.display().to_string())
+                .unwrap_or_default(),
+            e
+        );
+        exit(0x0004);
+    }
+    if let Some(e) = log_file_error {
+        error!("{}, not possible to continue", e);
+        exit(0x0004);
+    }
+    if let Some(log_file) = &log_file {
+        info!(
+            "Logging to {} at level {} as well",
+            log_file.path.display(),
+            log_file.level
+        );
+    }
     // Get the time measurements we use for reporing performance.
     let time_start = Local::now();
     let start_time = time_start.format("%Y-%m-%dT%H:%M:%S");
     info!("Starting the process at {start_time}");
 
     info!("File to parse: {}", args.location_video_list);
-    info!("Download tool to use: {}", args.video_download_tool);
-    info!(
-        "Move target set to '{}', '' means none set",
-        args.move_target
-    );
     // Get the current version, this is baked into the application and can be extracted as a ENV var
 
     info!("Running version {}", VERSION);
-    let yt_downloader_is_present = check_downloader_present(args.video_download_tool.clone());
-    if !yt_downloader_is_present {
-        error!(
-            "{} is not present, not possible to continue",
-            args.video_download_tool
-        );
-        exit(0x0002);
+    let config = match config_result {
+        Ok(config) => {
+            for file in &config.files {
+                info!("Loaded config file {}", file.display());
+            }
+            config
+        }
+        Err(e) => {
+            error!("{}, not possible to continue", e);
+            exit(0x0004);
+        }
+    };
+    info!("Download tool to use: {}", config.download_tool);
+    info!("Number of parallel downloads: {}", config.jobs);
+    // Without the handler Ctrl-C still works, it just leaves the downloads and the folder as they are.
+    let shutdown = match Shutdown::install() {
+        Ok(shutdown) => shutdown,
+        Err(e) => {
+            warn!("Could not install the Ctrl-C handler: {}", e);
+            Shutdown::default()
+        }
+    };
+    let download_tool = match resolve_downloader(&config.download_tool) {
+        Some(path) => path,
+        None => {
+            error!(
+                "{} is not present, not possible to continue",
+                config.download_tool
+            );
+            exit(0x0002);
+        }
+    };
+    info!("Download tool resolved to {}", download_tool.display());
+    let download_tool_version = tool_version(&download_tool, &shutdown);
+    match &download_tool_version {
+        Some(version) => info!("Download tool version: {}", version),
+        None => warn!("Could not get the version of {}", download_tool.display()),
     }
-    // Create a folder with the current datetime
+    let profile_args = match config.profile_args(&config.profile) {
+        Some(profile_args) => profile_args,
+        None => {
+            error!(
+                "Profile {} is not defined, known profiles are {:?}",
+                config.profile,
+                config.profile_names()
+            );
+            exit(0x0004);
+        }
+    };
+    info!("Using profile {}: {:?}", config.profile, profile_args);
+    let archive = match &args.download_archive {
+        Some(path) => match Archive::load(Path::new(path)) {
+            Ok(archive) => Some(Arc::new(archive)),
+            Err(e) => {
+                error!(
+                    "Could not read the download archive {}: {}, not possible to continue",
+                    path, e
+                );
+                exit(0x0004);
+            }
+        },
+        None => None,
+    };
+    // Create the download folder in the work dir, by default a folder with the current date in the current folder
     debug!("Starting creating source folder");
     let datetime = Local::now();
-    let folder_name: String = datetime.format("%Y%m%d").to_string();
-    debug!("About to create folder {}", &folder_name);
-    let create_folder_result = fs::create_dir(&folder_name);
-    match create_folder_result {
-        Ok(_) => {
-            debug!("Source folder created")
+    let work_dir = PathBuf::from(config.work_dir.as_deref().unwrap_or_default());
+    let name = match &args.resume {
+        Some(folder) if !folder.is_empty() => folder.clone(),
+        // Every run has its own id, so the folder of today cannot be found again.
+        Some(_) if config.folder_name.contains("{run_id}") => {
+            error!(
+                "The folder name {} has the run id in it, pass the folder of the batch to --resume",
+                config.folder_name
+            );
+            exit(0x0004);
         }
-        Err(e) => {
-            warn!(
-                "Could not create the folder, it may already exist, will try to continue: {:?}",
-                e
-            )
+        _ => {
+            let tokens = FolderTokens {
+                list_name: Path::new(&args.location_video_list)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                run_id: run_id().to_string(),
+                hostname: hostname(),
+            };
+            match render_folder_name(&config.folder_name, &datetime, &tokens) {
+                Ok(name) => name,
+                Err(e) => {
+                    error!("{}, not possible to continue", e);
+                    exit(0x0004);
+                }
+            }
         }
+    };
+    if !work_dir.as_os_str().is_empty()
+        && let Err(e) = fs::create_dir_all(&work_dir)
+    {
+        error!(
+            "Could not create the work dir {}: {}, not possible to continue",
+            work_dir.display(),
+            e
+        );
+        exit(0x0004);
+    }
+    let mut folder_path = work_dir.join(name);
+    // Without a folder the batch is found by the name template, the collision policy may have put it in a folder with
+    // a counter after the name.
+    if args.resume.as_deref() == Some("") {
+        folder_path = match resumable_folder(&folder_path) {
+            Ok(folder) => folder,
+            Err(e) => {
+                error!("{}, not possible to continue", e);
+                exit(0x0004);
+            }
+        };
+    } else if args.resume.is_some() && !folder_path.is_dir() {
+        // A folder passed to --resume has to be there, creating it would start the batch over under a mistyped name.
+        error!(
+            "There is no batch to resume, the folder {} does not exist, not possible to continue",
+            folder_path.display()
+        );
+        exit(0x0004);
     }
+    debug!("About to create folder {}", folder_path.display());
+    // A resumed batch goes on in the folder it was in, for a new batch the folder that is there is up to the policy.
+    let folder_policy = if args.resume.is_some() {
+        CollisionPolicy::Merge
+    } else {
+        config.collision
+    };
+    let folder_name = match create_download_folder(&folder_path, folder_policy) {
+        Ok(folder) => folder.display().to_string(),
+        Err(e) => {
+            error!("{}, not possible to continue", e);
+            exit(0x0004);
+        }
+    };
+    info!("Downloading to {}", folder_name);
+
+    let failed_list = match format_date_pattern(&args.failed_list, &datetime) {
+        Ok(failed_list) => failed_list,
+        Err(e) => {
+            error!("{}, not possible to continue", e);
+            exit(0x0004);
+        }
+    };
+    info!("Failed urls will be written to {}", failed_list);
+    // The partial files of the batch are left alone on a resume, yt-dlp continues them where they stopped.
+    let report_path = match args
+        .report
+        .as_deref()
+        .map(|r| format_date_pattern(r, &datetime))
+    {
+        Some(Ok(report_path)) => Some(report_path),
+        Some(Err(e)) => {
+            error!("{}, not possible to continue", e);
+            exit(0x0004);
+        }
+        None => None,
+    };
+    let state = if args.resume.is_some() {
+        info!("Resuming the batch in {}", folder_name);
+        match BatchState::load(&folder_name) {
+            Ok(state) => state,
+            Err(e) => {
+                error!("{}, not possible to continue", e);
+                exit(0x0004);
+            }
+        }
+    } else {
+        BatchState::new(&folder_name)
+    };
+    let unfinished_list = match format_date_pattern(&args.unfinished_list, &datetime) {
+        Ok(unfinished_list) => unfinished_list,
+        Err(e) => {
+            error!("{}, not possible to continue", e);
+            exit(0x0004);
+        }
+    };
 
     // Open the file, gets the name from the params or it takes the default.
     debug!(
@@ -213,13 +756,40 @@ fn main() -> io::Result<()> {
         args.location_video_list
     );
     let file = File::open(args.location_video_list.clone());
-    match file {
+    let (results, move_outcome) = match file {
         Ok(fs) => {
             info!("File found and opened");
-            let process_result = process_videos(&folder_name, fs, &args.move_target);
+            let settings = BatchSettings {
+                move_target: evaluate_move_path(config.move_target.as_deref()),
+                collision: config.collision,
+                conflict: config.conflict,
+                jobs: config.jobs,
+                downloaders: Arc::new(Downloaders::from_config(
+                    download_tool,
+                    &config,
+                    &config.profile,
+                )),
+                archive,
+                failed_list: PathBuf::from(&failed_list),
+                retry_policy: RetryPolicy {
+                    max_attempts: args.max_attempts.max(1),
+                    initial_delay: std::time::Duration::from_secs(args.retry_delay),
+                    max_delay: std::time::Duration::from_secs(MAX_RETRY_DELAY_SECONDS),
+                },
+                time_limits: TimeLimits {
+                    timeout: minutes_to_limit(args.timeout),
+                    stall_timeout: minutes_to_limit(args.stall_timeout),
+                },
+                unfinished_list: PathBuf::from(&unfinished_list),
+                shutdown: shutdown.clone(),
+                state: Arc::new(state),
+                progress_bars,
+            };
+            let process_result = process_videos(&folder_name, fs, &settings);
             match process_result {
-                Ok(_) => {
-                    info!("Processing video completed")
+                Ok(outcome) => {
+                    info!("Processing video completed");
+                    outcome
                 }
                 Err(e) => {
                     error!("Processing videos encountered an error: {:?}", e);
@@ -234,7 +804,7 @@ fn main() -> io::Result<()> {
             );
             exit(0x0001);
         }
-    }
+    };
 
     trace!("Getting the end time");
     let time_end = Local::now();
@@ -245,6 +815,36 @@ fn main() -> io::Result<()> {
         "Process concluded at {end_time} while started at {start_time} it took {} hours",
         render_duration_readable(time_passed),
     );
+    let failed_downloads = results.iter().filter(|r| r.failed_download()).count();
+    if failed_downloads > 0 {
+        error!("{} download(s) failed", failed_downloads);
+    }
+    let exit_code = if shutdown.stop_requested() {
+        warn!("The batch was stopped before all the downloads were done");
+        INTERRUPTED_EXIT_CODE
+    } else if failed_downloads > 0 {
+        0x0005
+    } else {
+        0
+    };
+    if let Some(report_path) = report_path {
+        let report = RunReport::new(
+            time_start,
+            time_end,
+            &folder_name,
+            &results,
+            move_outcome,
+            download_tool_version,
+            exit_code,
+        );
+        match report.write(Path::new(&report_path)) {
+            Ok(_) => info!("Wrote the report of the run to {}", report_path),
+            Err(e) => error!("Could not write the report to {}: {}", report_path, e),
+        }
+    }
+    if exit_code != 0 {
+        exit(exit_code);
+    }
     Ok(())
 }
 
@@ -253,143 +853,236 @@ fn main() -> io::Result<()> {
 /// # Parameters
 /// file - Handle to a file that has the yt urls as a `\n` separated list.<br/>
 /// folder_name - The string that has the path of the directory to download to<br/>
-/// move_target - The string of the path you want to move the files to when done downloaded<br/>
+/// settings - How to download and where to move the downloads to<br/>
 /// ## Return
-/// Nothing on ok, and a generic Error object on error.
+/// The result of every download and how the move went, None when there was no move target, on ok, and a generic
+/// Error object on error.
 fn process_videos(
     folder_name: &String,
     file: File,
-    move_target: &String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Create a vector to store the lines that consists of urls to a youtube (or other) clip. Note it is mutable as we add to it.
-    let mut lines: Vec<String> = Vec::new();
+    settings: &BatchSettings,
+) -> Result<(Vec<JobResult>, Option<MoveOutcome>), Box<dyn std::error::Error>> {
     trace!("In the process vidoes method");
-    // Read the file line by line, if valid, add it to the lines collection to parse later. Ignore the empty lines and
-    // lines that fail from some other reason.
-    // We collect the lines, then enumerate it to add an index we use for logging, filter out empty lines and lines that
-    // fail for another reason. then use the for each to process the ones that passed through the filter.
-    io::BufReader::new(file)
-        .lines()
-        .enumerate()
-        .filter(|(_, line)| line.as_ref().unwrap_or(&"".to_string()).len() > 1)
-        .for_each(|(ix, line)| match line {
-            Ok(line) => {
+    let downloaders = &settings.downloaders;
+    let archive = &settings.archive;
+    let failed_list = settings.failed_list.as_path();
+    // Read the file line by line, the comments and blank lines are skipped, the lines that are not a valid url or
+    // have bad options are rejected. We check the profiles here as well as the list reader does not know them.
+    let (entries, mut rejected) = read_video_list(io::BufReader::new(file));
+    let mut lines: Vec<VideoEntry> = Vec::new();
+    for entry in entries {
+        match &entry.profile {
+            Some(profile) if !downloaders.knows_profile(profile) => rejected.push(RejectedLine {
+                line_number: entry.line_number,
+                line: entry.url.clone(),
+                reason: format!("Profile {profile} is not defined"),
+            }),
+            _ => {
                 debug!(
                     "Added line {}, index: {} to the list to download",
-                    line,
-                    ix + 1
+                    entry.url, entry.line_number
                 );
-                lines.push(line)
+                lines.push(entry)
             }
-            Err(e) => {
-                warn!(
-                    "Could not parse line {}, skipping it, error: {}",
-                    ix + 1,
-                    e.to_string()
+        }
+    }
+    // The same video can be in the list in different shapes, downloading it twice at the same time makes the
+    // downloads fight over the same files, so only the first one is kept when they have the same options.
+    let (mut lines, merged) = dedupe_entries(lines);
+    for (entry, kept_line) in &merged {
+        info!(
+            "Line {} ({}) is the same as line {}, merged into it",
+            entry.line_number, entry.url, kept_line
+        );
+    }
+    // Skip what has been downloaded in an earlier run, this only works for the urls that carry their video id.
+    if let Some(archive) = &archive {
+        lines.retain(|entry| match archive_key_for_url(&entry.url) {
+            Some(key) if archive.contains(&key) => {
+                info!(
+                    "Line {} ({}) is in the download archive, skipping it",
+                    entry.line_number, entry.url
                 );
+                false
             }
+            _ => true,
         });
+    }
+    // Skip what was downloaded before the batch was cut off, when resuming.
+    let state = &settings.state;
+    lines.retain(|entry| {
+        let done = state.is_done(entry);
+        if done {
+            info!(
+                "Line {} ({}) was downloaded before the batch was cut off, skipping it",
+                entry.line_number, entry.url
+            );
+        }
+        !done
+    });
+    if let Err(e) = state.start(&lines) {
+        warn!(
+            "Could not write the batch state, it cannot be resumed: {}",
+            e
+        );
+    }
+    // Tell the user about all the lines we skip before the downloads start, so they do not get lost in the logging.
+    if !rejected.is_empty() {
+        rejected.sort_by_key(|r| r.line_number);
+        warn!(
+            "{} line(s) of the video list are rejected and will not be downloaded:",
+            rejected.len()
+        );
+        for r in &rejected {
+            warn!("  line {}: {} ({})", r.line_number, r.reason, r.line.trim());
+        }
+    }
 
     // Some information we want to keep track of to tell the user where we are in the process.
     let number_of_items = lines.len();
     debug!("Number of items to process: {}", number_of_items);
     let mut iterator_items_index = 1;
-    // Setup the communication with the threads, we create the number of download channels and they are passed one for
-    // one in the threads.
-    let (tx, rx) = sync_channel(lines.len());
+    // Setup the communication with the threads, the workers report back over this channel how the downloads are
+    // going and when a download is done.
+    let (tx, rx) = sync_channel(lines.len() + PROGRESS_BUFFER);
+    // The work queue, the urls are put on this channel and the workers pull the next url off it when they are done
+    // with the previous one. The receiver is shared between the workers so it needs to be behind a mutex.
+    let (job_tx, job_rx) = channel::<VideoEntry>();
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    // No point in starting more workers than there are urls to process.
+    let number_of_workers = settings.jobs.clamp(1, number_of_items.max(1));
+    info!(
+        "Starting {} workers for {} items",
+        number_of_workers, number_of_items
+    );
     // Create an array that can hold all the thread handles so we can join them down the line
     let mut thread_pool = vec![];
-    // Process the lines in the array, create a thread for each download
-    for line in &lines {
-        // We move the transmitter into the thread, so we need to create a clone if it, as it is a Arc it will just
-        // increase the reference counter.
-        let tx = tx.clone();
-        // Do something with each line
-        info!("Processing {}", line);
-        // We move the line into the thread, so we cannot use it anymore after that, so we make a
-        // clone that enables us to grab it later. Same story for the folder name. We use it for all
-        // the threads so we cannot move the original. The String::from is also a way of cloning the
-        // string but also casting it from &str to String.
-        let cline = line.clone();
-        let cfn = String::from(folder_name);
-        let t = thread::spawn(move || {
-            // Run yt-dlp process with the line as an argument, by default we remove the
-            // sponsor-blocks as they are repetitive and most of the time not even relevant
-            // I am not using the output in normal use, so I prepend it with a "_". For debugging
-            // it can be useful. The in and output are buffered
-            debug!(
-                "I am in thread {:?} starting downloading {}",
-                thread::current().id(),
-                &cline
-            );
-            let output = Command::new("yt-dlp")
-                .arg("--sponsorblock-remove")
-                .arg("default")
-                .arg("--retries")
-                .arg("infinite")
-                .arg("--fragment-retries")
-                .arg("infinite")
-                .arg("--buffer-size")
-                .arg("16K")
-                .arg("--no-check-certificate")
-                .arg(&cline)
-                .current_dir(cfn)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .output()
-                .expect(
-                    "Failed to execute yt-dlp command, you may need to (re)install it. \
-                Or make sure it is in PATH of this executable",
-                );
-            debug!(
-                "I am in thread {:?} completed downloading {}",
-                thread::current().id(),
-                &cline
-            );
-
-            trace!(
-                "Thread {:?} StOut: {:?}",
-                thread::current().id(),
-                String::from_utf8(output.stdout).unwrap()
-            );
-            trace!(
-                "Thread {:?} StErr: {:?}",
-                thread::current().id(),
-                String::from_utf8(output.stderr).unwrap()
-            );
-            trace!(
-                "About the sent message to main thread from thread {:?}",
-                thread::current().id()
-            );
-            tx.send(format!("Downloaded {}", &cline))
-                .expect("Could not sent message");
-            trace!(
-                "Message to main thread from thread {:?} sent",
-                thread::current().id()
-            );
-        });
+    for worker_index in 1..=number_of_workers {
+        // We move the transmitter and the queue into the thread, so we need to create a clone of them, as they are
+        // Arc-ed it will just increase the reference counter.
+        let context = WorkerContext {
+            folder_name: String::from(folder_name),
+            archive: archive.clone(),
+            retry_policy: settings.retry_policy,
+            time_limits: settings.time_limits,
+            shutdown: settings.shutdown.clone(),
+            messages: tx.clone(),
+        };
+        let job_rx = Arc::clone(&job_rx);
+        let downloaders = Arc::clone(downloaders);
+        let state = Arc::clone(&settings.state);
+        let t = thread::Builder::new()
+            .name(format!("worker-{worker_index}"))
+            .spawn(move || {
+                loop {
+                    // After Ctrl-C the urls left in the queue are not started, they go to the unfinished list.
+                    if context.shutdown.stop_requested() {
+                        debug!("Stop requested, {:?} stops", thread::current().name());
+                        break;
+                    }
+                    // Only hold the lock while taking the next url off the queue, otherwise the other workers
+                    // would wait for this download to finish. When the queue is empty and closed we are done.
+                    let next_job = job_rx.lock().expect("Job queue lock poisoned").recv();
+                    let Ok(entry) = next_job else {
+                        break;
+                    };
+                    set_job_context(Some(JobContext {
+                        url: entry.url.clone(),
+                        job_id: entry.job_id(),
+                        attempt: 1,
+                    }));
+                    if let Err(e) = state.running(&entry) {
+                        warn!("Could not write the batch state: {}", e);
+                    }
+                    let result =
+                        download_with_retries(downloaders.for_url(&entry.url), &entry, &context);
+                    if let Err(e) = state.finished(&entry, &result) {
+                        warn!("Could not write the batch state: {}", e);
+                    }
+                    set_job_context(None);
+                    trace!(
+                        "About the sent message to main thread from thread {:?}",
+                        thread::current().name()
+                    );
+                    context
+                        .messages
+                        .send(WorkerMessage::Finished(result))
+                        .expect("Could not sent message");
+                    trace!(
+                        "Message to main thread from thread {:?} sent",
+                        thread::current().name()
+                    );
+                }
+                debug!("Work queue is empty, {:?} stops", thread::current().name());
+            })?;
         // End thread creation.
-        info!(
-            "Created thread {:?} for youtube url {line}",
-            t.thread().id()
-        );
+        info!("Created worker thread {:?}", t.thread().name());
         thread_pool.push(t);
     }
+    // Keep the entries to be able to write the failed ones back with their options.
+    let entries_by_line: HashMap<usize, VideoEntry> = lines
+        .iter()
+        .map(|entry| (entry.line_number, entry.clone()))
+        .collect();
+    // Fill the queue, the workers are already waiting for it.
+    for line in lines {
+        info!("Processing {}", line.url);
+        job_tx.send(line)?;
+    }
+    // Close the queue so the workers leave their loop when it has been drained.
+    drop(job_tx);
     // Need to drop the transmitter as otherwise the receiver never stops listening.
     // It will keep tx alive to process the outstanding receivers, if these are all sent,
     // the channels are dropped and the while loop below will exit.
     drop(tx);
     // While there a channels open, wait till they all have sent their message, then when there are
     // none left, the recv will fail (We dropped the transmitter above) and we leave the loop as there would be no Ok() message.
-    while let Ok(msg) = rx.recv() {
-        info!("{msg}, {iterator_items_index} from {number_of_items}");
+    let mut results: Vec<JobResult> = Vec::new();
+    let mut display = ProgressDisplay::new(&settings.progress_bars, number_of_items);
+    while let Ok(message) = rx.recv() {
+        let result = match message {
+            WorkerMessage::Progress {
+                line_number,
+                url,
+                progress,
+            } => {
+                trace!(
+                    "Line {} ({}): {}",
+                    line_number,
+                    url,
+                    render_progress(&progress)
+                );
+                display.update(line_number, &url, &progress);
+                continue;
+            }
+            WorkerMessage::Finished(result) => result,
+        };
+        display.finish(&result);
+        if result.success {
+            info!(
+                "Downloaded {}, {iterator_items_index} from {number_of_items}",
+                result.url
+            );
+        } else if result.interrupted {
+            warn!(
+                "Stopped {}, {iterator_items_index} from {number_of_items}",
+                result.url
+            );
+        } else {
+            warn!(
+                "Failed {}, {iterator_items_index} from {number_of_items}",
+                result.url
+            );
+        }
+        results.push(result);
         iterator_items_index += 1;
     }
+    display.close();
     // Join all threads to we can start moving when all downloads have been completed. Note we clone the ids as they are
     // moved after use.
     for t in thread_pool {
-        let current_thread = t.thread().id().clone();
-        trace!("About the join thread {:?}", t.thread().id().clone());
+        let current_thread = t.thread().id();
+        trace!("About the join thread {:?}", current_thread);
         let join_result = t.join();
         // Use the join result to inquire the result. The thread variable is dropped after join.
         match join_result {
@@ -407,22 +1100,99 @@ fn process_videos(
         trace!("Joined thread {:?}", current_thread);
     }
 
-    // Rust has some useful constants baked in, one of them is the OS that holds the OS it is running on.
-    let os_running = env::consts::OS;
+    if !results.is_empty() {
+        info!("Summary of the downloads:");
+        for line in render_summary(&results) {
+            info!("{line}");
+        }
+    }
+    // Write the failed ones to a list that can be run again.
+    let failed: Vec<(VideoEntry, String)> = results
+        .iter()
+        .filter(|r| r.failed_download())
+        .filter_map(|r| {
+            entries_by_line.get(&r.line_number).map(|entry| {
+                let reason = r.error().unwrap_or("failed without an error message");
+                (entry.clone(), reason.to_string())
+            })
+        })
+        .collect();
+    if !failed.is_empty() {
+        match write_video_list(failed_list, &failed) {
+            Ok(_) => info!(
+                "Wrote {} failed url(s) to {}, run again with -l {}",
+                failed.len(),
+                failed_list.display(),
+                failed_list.display()
+            ),
+            Err(e) => error!(
+                "Could not write the failed urls to {}: {}",
+                failed_list.display(),
+                e
+            ),
+        }
+    }
 
-    // Default when running linux, I run Arch by the way 😎
-    debug!(
-        "Set the path to linux path as default, other OS will overwrite, the current OS is {os_running}"
-    );
-    // The path will set the move path here, either by attribute '-m' or the defaults set by OS. The defaults may not
-    // make sense on your system so change them if needed.
-    let path_to_nas = evaluate_move_path(os_running, move_target);
+    // When the batch was stopped, write what did not get downloaded to a list to run later: the urls that were
+    // stopped halfway and the ones that were never started.
+    if settings.shutdown.stop_requested() {
+        let finished: HashMap<usize, &JobResult> = results
+            .iter()
+            .filter(|r| !r.interrupted)
+            .map(|r| (r.line_number, r))
+            .collect();
+        let mut unfinished: Vec<(VideoEntry, String)> = entries_by_line
+            .values()
+            .filter(|entry| !finished.contains_key(&entry.line_number))
+            .map(|entry| {
+                let reason = if results.iter().any(|r| r.line_number == entry.line_number) {
+                    "stopped, the batch was interrupted"
+                } else {
+                    "not started, the batch was interrupted"
+                };
+                (entry.clone(), reason.to_string())
+            })
+            .collect();
+        unfinished.sort_by_key(|(entry, _)| entry.line_number);
+        if !unfinished.is_empty() {
+            match write_video_list(&settings.unfinished_list, &unfinished) {
+                Ok(_) => warn!(
+                    "Wrote {} unfinished url(s) to {}, run again with -l {}",
+                    unfinished.len(),
+                    settings.unfinished_list.display(),
+                    settings.unfinished_list.display()
+                ),
+                Err(e) => error!(
+                    "Could not write the unfinished urls to {}: {}",
+                    settings.unfinished_list.display(),
+                    e
+                ),
+            }
+        }
+    }
+
+    // The batch ran to its end, what is not done is in the failed or unfinished list, so the state can go. It would
+    // otherwise be moved along with the downloads.
+    if let Err(e) = settings.state.remove() {
+        warn!("Could not remove the batch state: {}", e);
+    }
+
+    // The move target comes from '-m' or the config, without one the downloads stay in the download folder.
+    let Some(path_to_nas) = settings.move_target.clone() else {
+        info!("No move target set, the downloads stay in {}", folder_name);
+        return Ok((results, None));
+    };
 
     // Using the MacOS/Linux move tool here, there are ways to do this in Rust but it is a bit
     // cumbersome and I did not feel like reinventing the mv statement.
     debug!("Going into the move result function");
     let move_time_start = Local::now();
-    let move_result = move_to_nas(folder_name.clone(), format!("{}", path_to_nas));
+    let (move_result, decisions) = move_to_nas(
+        folder_name.clone(),
+        path_to_nas.clone(),
+        settings.collision,
+        settings.conflict,
+    );
     trace!("Evaluating result move {:?}", move_result);
     if move_result {
         info!("Move complete")
@@ -432,34 +1202,369 @@ fn process_videos(
     let move_time_end = Local::now();
     let move_time = move_time_end - move_time_start;
     info!("Move took {} hours", render_duration_readable(move_time));
-    Ok(())
+    let move_outcome = MoveOutcome {
+        target: path_to_nas,
+        success: move_result,
+        duration: move_time.to_std().unwrap_or_default(),
+        decisions,
+    };
+    Ok((results, Some(move_outcome)))
+}
+
+/// Download a single url with the backend into the folder, this is what the workers run for every url they
+/// take off the work queue.
+/// # Parameters
+/// downloader - The backend that handles the url<br/>
+/// entry - The line of the video list to download<br/>
+/// context - Where to download to, the limits of the tool and the channel to report the progress on<br/>
+/// attempt - The number of the attempt, for the log of the download<br/>
+/// # Returns
+/// The result of the download, a failed result when the tool could not be started.
+fn download_video(
+    downloader: &dyn Downloader,
+    entry: &VideoEntry,
+    context: &WorkerContext,
+    attempt: u32,
+) -> JobResult {
+    let url = &entry.url;
+    let output_dir = entry.output_dir(&context.folder_name);
+    if let Err(e) = fs::create_dir_all(&output_dir) {
+        warn!(
+            "Could not create {} for {}: {}",
+            output_dir.display(),
+            url,
+            e
+        );
+        return JobResult::failed(
+            url,
+            entry.line_number,
+            downloader.name(),
+            format!("Could not create {}: {}", output_dir.display(), e),
+        );
+    }
+    // The in and output are buffered, we use them to find out what has been written and for tracing.
+    debug!(
+        "I am in thread {:?} starting downloading {} with {}",
+        thread::current().name(),
+        url,
+        downloader.name()
+    );
+    let mut command = downloader.build_command(entry, &output_dir);
+    trace!("Running {:?}", command);
+    // The output of the tool is kept per download, to look into a failure without running the batch again at trace.
+    let log_path = job_log_path(&context.folder_name, &run_id().to_string(), entry);
+    let write_log =
+        |command: &Command, outcome: &str, stdout: &[u8], stderr: &[u8]| match append_job_log(
+            &log_path, attempt, command, outcome, stdout, stderr,
+        ) {
+            Ok(_) => Some(log_path.display().to_string()),
+            Err(e) => {
+                warn!(
+                    "Could not write the tool output to {}: {}",
+                    log_path.display(),
+                    e
+                );
+                None
+            }
+        };
+    let started = Instant::now();
+    // Pass the progress the tool reports on to the main thread, a report that does not fit in the channel is dropped
+    // as the next one is never far off.
+    let mut report_progress = |line: &str| {
+        if let Some(progress) = downloader.parse_progress(line) {
+            let _ = context.messages.try_send(WorkerMessage::Progress {
+                line_number: entry.line_number,
+                url: url.clone(),
+                progress,
+            });
+        }
+    };
+    let output = match run_tool(
+        &mut command,
+        &context.time_limits,
+        &context.shutdown,
+        &mut report_progress,
+    ) {
+        Ok(output) => output,
+        Err(e) => {
+            error!(
+                "Failed to execute {} command, you may need to (re)install it. \
+                Or make sure it is in PATH of this executable: {}",
+                downloader.name(),
+                e
+            );
+            let reason = format!("Could not start {}: {}", downloader.name(), e);
+            let mut result = JobResult::failed(url, entry.line_number, downloader.name(), reason);
+            result.log_file = write_log(&command, &result.stderr_tail.join("\n"), &[], &[]);
+            return result;
+        }
+    };
+    let duration = started.elapsed();
+    let outcome = match (&output.killed, &output.status) {
+        (Some(reason), _) => reason.message(),
+        (None, Some(status)) => status.to_string(),
+        (None, None) => String::from("no exit status"),
+    };
+    let log_file = write_log(&command, &outcome, &output.stdout, &output.stderr);
+    debug!(
+        "I am in thread {:?} completed downloading {}",
+        thread::current().name(),
+        url
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    trace!("Thread {:?} StOut: {:?}", thread::current().name(), stdout);
+    trace!("Thread {:?} StErr: {:?}", thread::current().name(), stderr);
+    let succeeded = output
+        .status
+        .is_some_and(|status| downloader.interpret_status(&status));
+    // The tool reports the files relative to the folder it ran in, a subdir of the download folder when the entry
+    // has one.
+    let output_files: Vec<String> = downloader
+        .parse_output(&stdout)
+        .into_iter()
+        .map(|file| match &entry.subdir {
+            Some(subdir) => Path::new(subdir).join(file).display().to_string(),
+            None => file,
+        })
+        .collect();
+    let bytes = output_files
+        .iter()
+        .filter_map(|file| fs::metadata(Path::new(&context.folder_name).join(file)).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    if succeeded {
+        debug!("Files written for {}: {:?}", url, output_files);
+        if let Some(archive) = &context.archive {
+            let mut keys = downloader.archive_keys(&stdout);
+            keys.extend(archive_key_for_url(url));
+            for key in keys {
+                if let Err(e) = archive.record(&key) {
+                    warn!("Could not add {} to the download archive: {}", key, e);
+                }
+            }
+        }
+    } else {
+        match (&output.killed, &output.status) {
+            (Some(reason), _) => warn!("{} for {}", reason.message(), url),
+            (None, Some(status)) => warn!(
+                "{} reported a failure for {}: {}",
+                downloader.name(),
+                url,
+                status
+            ),
+            (None, None) => warn!("{} ended without a status for {}", downloader.name(), url),
+        }
+    }
+    // When the tool was killed the reason goes last, it is the most telling line.
+    let mut stderr_lines = stderr_tail(&stderr);
+    if let Some(reason) = &output.killed {
+        stderr_lines.push(reason.message());
+    }
+    let interrupted = output.killed == Some(KillReason::Interrupted);
+    JobResult {
+        url: url.clone(),
+        line_number: entry.line_number,
+        tool: downloader.name().to_string(),
+        success: succeeded,
+        exit_code: output.status.and_then(|status| status.code()),
+        timed_out: output.killed.is_some() && !interrupted,
+        interrupted,
+        duration,
+        attempts: 1,
+        output_files,
+        bytes,
+        stderr_tail: stderr_lines,
+        log_file,
+    }
+}
+
+/// Download an entry, trying again with a growing delay when it fails for a reason that may go away, like a rate
+/// limit. Failures that will not go away, like a private or removed video, are not tried again.
+/// # Parameters
+/// downloader - The backend that handles the url<br/>
+/// entry - The line of the video list to download<br/>
+/// context - How often to try and the settings of the download, no retries are started once the user asks to
+/// stop<br/>
+/// # Returns
+/// The result of the last attempt, with the number of attempts and the time of all attempts.
+fn download_with_retries(
+    downloader: &dyn Downloader,
+    entry: &VideoEntry,
+    context: &WorkerContext,
+) -> JobResult {
+    let retry_policy = &context.retry_policy;
+    let shutdown = &context.shutdown;
+    let mut attempt = 1;
+    let mut total_duration = std::time::Duration::ZERO;
+    loop {
+        set_job_attempt(attempt);
+        let mut result = download_video(downloader, entry, context, attempt);
+        total_duration += result.duration;
+        result.duration = total_duration;
+        result.attempts = attempt;
+        if result.success
+            || result.interrupted
+            || !result.tool_ran()
+            || attempt >= retry_policy.max_attempts
+            || shutdown.stop_requested()
+        {
+            return result;
+        }
+        let failure_kind = classify_failure(&result.stderr_tail.join("\n"));
+        if !failure_kind.is_retryable() {
+            info!(
+                "{} failed permanently, not trying again: {}",
+                entry.url,
+                result.error().unwrap_or_default()
+            );
+            return result;
+        }
+        let delay = retry_policy.delay(attempt);
+        warn!(
+            "Attempt {} of {} for {} failed ({:?}), trying again in {:.1}s",
+            attempt,
+            retry_policy.max_attempts,
+            entry.url,
+            failure_kind,
+            delay.as_secs_f64()
+        );
+        if shutdown.sleep(delay) {
+            info!("Stop requested, not trying {} again", entry.url);
+            return result;
+        }
+        attempt += 1;
+    }
+}
+
+/// The log file settings from the `[log]` section of the config, the commandline options are a layer of it.
+/// # Parameters
+/// log_config - The `[log]` section of the config<br/>
+/// console_level - The level of the console, the log file has the same level unless it is set<br/>
+/// # Returns
+/// The log file settings, None when there is no log file, or an error message when a setting is not valid.
+fn log_file_settings(
+    log_config: &LogConfig,
+    console_level: &str,
+) -> Result<Option<LogFileSettings>, String> {
+    let Some(path) = &log_config.file else {
+        return Ok(None);
+    };
+    let level = log_config.level.as_deref().unwrap_or(console_level);
+    let level =
+        LevelFilter::from_str(level).map_err(|_| format!("'{level}' is not a valid log level"))?;
+    let rotation = log_config.rotate.as_deref().unwrap_or("daily");
+    Ok(Some(LogFileSettings {
+        path: PathBuf::from(path),
+        level,
+        rotation: parse_rotation(rotation)?,
+        keep: log_config.keep.unwrap_or(7),
+    }))
 }
 
-fn evaluate_move_path(os_running: &str, path_to_nas: &String) -> String {
-    // Set the ret_val scope, As all paths set it, we do not need to initialize it to anything.
-    let ret_val: String;
-    if path_to_nas.eq("") {
-        // Bit if a hack to format a standard windows path.
-
-        if os_running.eq("macos") {
-            debug!("Set the path as set in macos overwriting the linux path");
-            ret_val = String::from("/Volumes/huge/media/youtube/");
-        } else if os_running.eq("windows") {
-            debug!("Set the path as set in windows overwriting the linux path");
-            ret_val = String::from("H:/media/youtube/");
-        } else if os_running.eq("linux") {
-            debug!("Set the path as set in windows overwriting the linux path");
-            ret_val = String::from("/home/phiro/mac/huge/media/youtube/");
+/// The environment variables of the commandline options that are not config keys, clap reads them itself.
+fn argument_env_names() -> Vec<String> {
+    Args::command()
+        .get_arguments()
+        .filter_map(|arg| arg.get_env())
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// The options of the commandline that are also config keys, as the top layers of the config. An option that clap
+/// read from its environment variable gets a layer of its own, so `config show` tells the variable it came from.
+/// # Parameters
+/// args - The commandline arguments<br/>
+/// matches - What clap matched, it tells where every value came from<br/>
+/// # Returns
+/// The layers of the variables, in the order of the options, with the layer of the options that were passed on top.
+fn commandline_layers(args: &Args, matches: &ArgMatches) -> Vec<ConfigLayer> {
+    let mut layers = Vec::new();
+    let mut commandline = ConfigLayer::new("commandline");
+    let mut set = |id: &str, key: &str, value: Value| {
+        if matches.value_source(id) == Some(ValueSource::EnvVariable) {
+            let mut layer =
+                ConfigLayer::new(&format!("environment YT_PARALLEL_{}", id.to_uppercase()));
+            layer.set(key, value);
+            layers.push(layer);
         } else {
-            debug!("Did not recognize os: {} just move it to temp", os_running);
-            ret_val = String::from("/tmp/");
+            commandline.set(key, value);
         }
-        info!(
-            "There is no parameter passed path set for the move target, so we use the default: {ret_val}"
+    };
+    if let Some(move_target) = &args.move_target {
+        set("move_target", "move-target", move_target.as_str().into());
+    }
+    if let Some(work_dir) = &args.work_dir {
+        set("work_dir", "work-dir", work_dir.as_str().into());
+    }
+    if let Some(folder_name) = &args.folder_name {
+        set("folder_name", "folder-name", folder_name.as_str().into());
+    }
+    if let Some(collision) = &args.collision {
+        set("collision", "collision", collision.name().into());
+    }
+    if let Some(conflict) = &args.conflict {
+        set("conflict", "conflict", conflict.name().into());
+    }
+    if let Some(tool) = &args.video_download_tool {
+        set("video_download_tool", "download-tool", tool.as_str().into());
+    }
+    if let Some(jobs) = args.jobs {
+        set("jobs", "jobs", (jobs as i64).into());
+    }
+    if let Some(profile) = &args.profile {
+        set("profile", "profile", profile.as_str().into());
+    }
+    if let Some(file) = &args.log_file {
+        set("log_file", "log.file", file.as_str().into());
+    }
+    if let Some(level) = &args.log_file_level {
+        set(
+            "log_file_level",
+            "log.level",
+            get_string_from_loglevel(level.clone())
+                .to_lowercase()
+                .into(),
         );
-    } else {
-        info!("Move path set for the move target by argument: {path_to_nas}");
-        ret_val = String::from(path_to_nas);
     }
-    ret_val
+    if let Some(rotate) = &args.log_rotate {
+        set("log_rotate", "log.rotate", rotate.as_str().into());
+    }
+    if let Some(keep) = args.log_keep {
+        set("log_keep", "log.keep", (keep as i64).into());
+    }
+    layers.push(commandline);
+    layers
+}
+
+/// Turn a limit in minutes from the arguments into a time limit, 0 is no limit
+fn minutes_to_limit(minutes: u64) -> Option<std::time::Duration> {
+    (minutes > 0).then(|| std::time::Duration::from_secs(minutes * 60))
+}
+
+/// The default number of parallel downloads, one per cpu the OS reports, falls back to 4 when that is unknown.
+/// # Returns
+/// The number of workers to use when `--jobs` is not passed.
+fn default_jobs() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+}
+
+/// The folder to move the downloads to, an empty move target is the same as none.
+/// # Parameters
+/// move_target - The move target of the commandline or the config<br/>
+/// # Returns
+/// The folder, None when the downloads stay where they are.
+fn evaluate_move_path(move_target: Option<&str>) -> Option<String> {
+    match move_target.filter(|target| !target.is_empty()) {
+        Some(target) => {
+            info!("Move path set for the move target: {target}");
+            Some(target.to_string())
+        }
+        None => {
+            debug!("There is no move target set, the downloads will not be moved");
+            None
+        }
+    }
 }
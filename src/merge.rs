@@ -0,0 +1,220 @@
+use fs_extra::file::{CopyOptions, move_file};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What to do with a file of the download folder that is already in the folder it is merged into
+#[derive(clap::ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// Leave the file in the download folder, the one in the target stays as it is
+    Skip,
+    /// Replace the file in the target
+    Overwrite,
+    /// Move the file in next to the one in the target with a counter after its name, like `video-2.mp4`
+    KeepBoth,
+    /// Replace the file in the target when the new one is larger, like a download that was cut off before
+    OverwriteIfLarger,
+}
+
+impl ConflictPolicy {
+    /// The name of the policy as it is written in the config and on the commandline
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConflictPolicy::Skip => "skip",
+            ConflictPolicy::Overwrite => "overwrite",
+            ConflictPolicy::KeepBoth => "keep-both",
+            ConflictPolicy::OverwriteIfLarger => "overwrite-if-larger",
+        }
+    }
+}
+
+/// What the merge did with a file
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeAction {
+    /// There was no such file in the target, it was moved in
+    Moved,
+    /// It was left in the download folder
+    Skipped,
+    /// It replaced the file in the target
+    Overwritten,
+    /// It was moved in under another name
+    KeptBoth,
+    /// It could not be moved, it is left in the download folder
+    Failed,
+}
+
+/// The decision the merge made for a file, for the report of the move
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MergeDecision {
+    /// The file, relative to the download folder
+    pub file: String,
+    pub action: MergeAction,
+    /// Where the file went, relative to the target, None when it was left in the download folder
+    pub destination: Option<String>,
+    pub bytes: u64,
+    /// The size of the file that was already in the target, None when there was none
+    pub existing_bytes: Option<u64>,
+    /// Why the file could not be moved, None when it did not fail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Move the files of a folder into a folder that already exists, file by file, keeping the folders inside it. What
+/// happens to a file that is already in the target is up to the conflict policy. A file that cannot be moved is
+/// recorded as failed and the merge goes on with the next one.
+/// # Parameters
+/// source - The folder to move the files out of, it is removed when all its files were moved<br/>
+/// target - The folder to move the files into, it is created when it is not there<br/>
+/// conflict - What to do with a file that is already in the target<br/>
+/// # Returns
+/// The decision for every file in the order of their names, or the error when the files to merge cannot be listed.
+pub fn merge_folder(
+    source: &Path,
+    target: &Path,
+    conflict: ConflictPolicy,
+) -> io::Result<Vec<MergeDecision>> {
+    let mut decisions = Vec::new();
+    for file in files_in(source)? {
+        let relative = file.strip_prefix(source).map_err(io::Error::other)?;
+        let decision = match merge_file(&file, relative, target, conflict) {
+            Ok(decision) => {
+                info!("{}", render_decision(&decision));
+                decision
+            }
+            Err(e) => {
+                let decision = MergeDecision {
+                    file: relative.display().to_string(),
+                    action: MergeAction::Failed,
+                    destination: None,
+                    bytes: fs::metadata(&file).map(|m| m.len()).unwrap_or_default(),
+                    existing_bytes: fs::metadata(target.join(relative)).ok().map(|m| m.len()),
+                    error: Some(e.to_string()),
+                };
+                warn!("{}", render_decision(&decision));
+                decision
+            }
+        };
+        decisions.push(decision);
+    }
+    // The decisions are made, a folder that cannot be cleaned up does not change them.
+    if let Err(e) = remove_empty_folders(source) {
+        warn!("Could not clean up {}: {}", source.display(), e);
+    }
+    Ok(decisions)
+}
+
+/// Move a file into the target, what happens when it is already there is up to the conflict policy.
+/// # Parameters
+/// file - The file in the download folder<br/>
+/// relative - The file relative to the download folder<br/>
+/// target - The folder to move the file into<br/>
+/// conflict - What to do when the file is already in the target<br/>
+/// # Returns
+/// The decision for the file, or the error that kept it from being moved.
+fn merge_file(
+    file: &Path,
+    relative: &Path,
+    target: &Path,
+    conflict: ConflictPolicy,
+) -> io::Result<MergeDecision> {
+    let bytes = fs::metadata(file)?.len();
+    let destination = target.join(relative);
+    let existing_bytes = fs::metadata(&destination).ok().map(|m| m.len());
+    let (action, destination) = match (existing_bytes, conflict) {
+        (None, _) => (MergeAction::Moved, Some(destination)),
+        (Some(_), ConflictPolicy::Overwrite) => (MergeAction::Overwritten, Some(destination)),
+        (Some(existing), ConflictPolicy::OverwriteIfLarger) if bytes > existing => {
+            (MergeAction::Overwritten, Some(destination))
+        }
+        (Some(_), ConflictPolicy::KeepBoth) => {
+            (MergeAction::KeptBoth, Some(free_name(&destination)))
+        }
+        (Some(_), _) => (MergeAction::Skipped, None),
+    };
+    if let Some(destination) = &destination {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        debug!("Moving {} to {}", file.display(), destination.display());
+        let mut options = CopyOptions::new();
+        options.overwrite = action == MergeAction::Overwritten;
+        move_file(file, destination, &options).map_err(io::Error::other)?;
+    }
+    Ok(MergeDecision {
+        file: relative.display().to_string(),
+        action,
+        destination: destination
+            .as_deref()
+            .and_then(|d| d.strip_prefix(target).ok())
+            .map(|d| d.display().to_string()),
+        bytes,
+        existing_bytes,
+        error: None,
+    })
+}
+
+/// Render a decision for the log, like `a.mp4: kept both as a-2.mp4 (12 bytes, 10 bytes were there)`
+pub fn render_decision(decision: &MergeDecision) -> String {
+    let action = match (decision.action, &decision.destination) {
+        (MergeAction::KeptBoth, Some(destination)) => format!("kept both as {destination}"),
+        (MergeAction::Skipped, _) => String::from("skipped, left in the download folder"),
+        (MergeAction::Failed, _) => format!(
+            "failed, left in the download folder: {}",
+            decision.error.as_deref().unwrap_or_default()
+        ),
+        (action, _) => format!("{action:?}").to_lowercase(),
+    };
+    match decision.existing_bytes {
+        Some(existing) => format!(
+            "{}: {} ({} bytes, {} bytes were there)",
+            decision.file, action, decision.bytes, existing
+        ),
+        None => format!("{}: {} ({} bytes)", decision.file, action, decision.bytes),
+    }
+}
+
+/// The first name with a counter after it that is not taken, `video.mp4` becomes `video-2.mp4`.
+fn free_name(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|counter| path.with_file_name(format!("{stem}-{counter}{extension}")))
+        .find(|candidate| !candidate.exists())
+        .expect("There is always a free counter")
+}
+
+/// All the files in a folder and the folders in it
+fn files_in(folder: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(files_in(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Remove the folders that were emptied by the merge, the folder itself included.
+fn remove_empty_folders(folder: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            remove_empty_folders(&path)?;
+        }
+    }
+    if fs::read_dir(folder)?.next().is_none() {
+        fs::remove_dir(folder)?;
+    }
+    Ok(())
+}
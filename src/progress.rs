@@ -0,0 +1,141 @@
+use std::time::Duration;
+
+/// The tags of the yt-dlp post processors that remove the sponsor segments
+const SPONSORBLOCK_TAGS: [&str; 2] = ["[SponsorBlock]", "[ModifyChapters]"];
+
+/// What the tool is busy with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    /// Looking up the video, before the download starts
+    Extracting,
+    Downloading,
+    /// Merging the video and audio streams into one file
+    Merging,
+    /// Cutting out the sponsor segments
+    SponsorBlock,
+    /// Any other work after the download, like extracting the audio or embedding the thumbnail
+    PostProcessing,
+}
+
+/// A progress report of the tool, the fields the line did not tell are None.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub phase: Phase,
+    pub percent: Option<f64>,
+    /// The size of the download, can be an estimate of the tool
+    pub total_bytes: Option<u64>,
+    pub downloaded_bytes: Option<u64>,
+    /// Bytes per second
+    pub speed: Option<u64>,
+    pub eta: Option<Duration>,
+}
+
+impl Progress {
+    /// A progress report that only tells the phase
+    pub fn phase(phase: Phase) -> Progress {
+        Progress {
+            phase,
+            percent: None,
+            total_bytes: None,
+            downloaded_bytes: None,
+            speed: None,
+            eta: None,
+        }
+    }
+}
+
+/// Parse a line of yt-dlp output into a progress report, like
+/// `[download]  42.3% of ~1.20GiB at  3.10MiB/s ETA 00:40 (frag 3/10)`.
+/// # Parameters
+/// line - A line of the standard output of yt-dlp
+/// # Returns
+/// The progress, None when the line does not tell anything about the progress.
+pub fn parse_yt_dlp_progress(line: &str) -> Option<Progress> {
+    let line = line.trim();
+    let (tag, rest) = match line.find(']') {
+        Some(end) if line.starts_with('[') => (&line[..=end], line[end + 1..].trim()),
+        _ => return None,
+    };
+    match tag {
+        "[download]" => parse_download_line(rest),
+        "[Merger]" => Some(Progress::phase(Phase::Merging)),
+        tag if SPONSORBLOCK_TAGS.contains(&tag) => Some(Progress::phase(Phase::SponsorBlock)),
+        // The post processors are named in CamelCase, the extractors in lower case, like [youtube] or [generic].
+        tag if tag[1..].starts_with(|c: char| c.is_ascii_uppercase()) => {
+            Some(Progress::phase(Phase::PostProcessing))
+        }
+        _ => Some(Progress::phase(Phase::Extracting)),
+    }
+}
+
+/// Parse what follows `[download]`, the percentage lines and the lines about the file being written.
+fn parse_download_line(rest: &str) -> Option<Progress> {
+    let mut words = rest.split_whitespace();
+    let Some(percent) = words
+        .next()
+        .and_then(|word| word.strip_suffix('%'))
+        .and_then(|number| number.parse::<f64>().ok())
+    else {
+        // Destination and resume lines, the download is about to start.
+        return Some(Progress::phase(Phase::Downloading));
+    };
+    let mut progress = Progress::phase(Phase::Downloading);
+    progress.percent = Some(percent);
+    while let Some(word) = words.next() {
+        match word {
+            "of" => {
+                // The size is prefixed with ~ when it is an estimate, with or without a space after it.
+                let size = match words.next() {
+                    Some("~") => words.next(),
+                    size => size,
+                };
+                progress.total_bytes = size.and_then(|s| parse_size(s.trim_start_matches('~')));
+            }
+            "at" => {
+                progress.speed = words
+                    .next()
+                    .and_then(|s| s.strip_suffix("/s"))
+                    .and_then(parse_size);
+            }
+            "ETA" => progress.eta = words.next().and_then(parse_clock),
+            _ => {}
+        }
+    }
+    progress.downloaded_bytes = progress
+        .total_bytes
+        .map(|total| (total as f64 * percent / 100.0) as u64);
+    Some(progress)
+}
+
+/// Parse a size like `1.20GiB`, `512.00KiB` or `100B`, yt-dlp writes the binary units.
+/// # Parameters
+/// size - The size with its unit
+/// # Returns
+/// The number of bytes, None for `Unknown` and anything else that is not a size.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let split = size.find(|c: char| c.is_ascii_alphabetic())?;
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let multiplier: u64 = match unit {
+        "B" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        "TiB" => 1 << 40,
+        "KB" | "kB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        _ => return None,
+    };
+    Some((number * multiplier as f64) as u64)
+}
+
+/// Parse a time like `00:40` or `01:02:03`
+fn parse_clock(clock: &str) -> Option<Duration> {
+    let mut seconds = 0u64;
+    for part in clock.split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+    Some(Duration::from_secs(seconds))
+}
@@ -0,0 +1,137 @@
+use crate::merge::MergeDecision;
+use crate::results::JobResult;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// How the move of the download folder went
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MoveOutcome {
+    /// The folder the downloads were moved to
+    pub target: String,
+    pub success: bool,
+    #[serde(rename = "duration_seconds", serialize_with = "as_seconds")]
+    pub duration: Duration,
+    /// What was done with every file when the folder was merged into the one at the target
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub decisions: Vec<MergeDecision>,
+}
+
+/// The report of a run, written as JSON with `--report` for the tools that cannot read the log.
+#[derive(Serialize, Debug)]
+pub struct RunReport {
+    /// The version of yt-parallel
+    pub version: String,
+    /// What the download tool printed for `--version`, null when it did not tell
+    pub tool_version: Option<String>,
+    /// The command line the run was started with
+    pub args: Vec<String>,
+    pub started: String,
+    pub finished: String,
+    #[serde(rename = "duration_seconds", serialize_with = "as_seconds")]
+    pub duration: Duration,
+    /// The folder the downloads went to before they were moved
+    pub download_folder: String,
+    pub downloads: Vec<DownloadReport>,
+    /// How the move went, null when there was no move target
+    #[serde(rename = "move")]
+    pub move_outcome: Option<MoveOutcome>,
+    pub downloaded: usize,
+    pub failed: usize,
+    pub stopped: usize,
+    pub exit_code: i32,
+}
+
+/// The result of one url of the video list
+#[derive(Serialize, Debug)]
+pub struct DownloadReport {
+    pub url: String,
+    pub line_number: usize,
+    pub tool: String,
+    /// ok, failed, timeout or stopped
+    pub status: String,
+    pub exit_code: Option<i32>,
+    pub attempts: u32,
+    pub duration_seconds: f64,
+    /// The size of the files that were written
+    pub bytes: u64,
+    pub output_files: Vec<String>,
+    pub error: Option<String>,
+    /// The file with the output of the tool
+    pub log_file: Option<String>,
+}
+
+impl RunReport {
+    /// Put the report of a run together
+    /// # Parameters
+    /// started - When the run started<br/>
+    /// finished - When the run was done<br/>
+    /// download_folder - The folder the downloads went to<br/>
+    /// results - The results of the downloads<br/>
+    /// move_outcome - How the move of the downloads went, None when they were not moved<br/>
+    /// tool_version - The version of the download tool, None when it is not known<br/>
+    /// exit_code - The exit code the run ends with<br/>
+    /// # Returns
+    /// The report, with the results in the order of the video list.
+    pub fn new(
+        started: DateTime<Local>,
+        finished: DateTime<Local>,
+        download_folder: &str,
+        results: &[JobResult],
+        move_outcome: Option<MoveOutcome>,
+        tool_version: Option<String>,
+        exit_code: i32,
+    ) -> RunReport {
+        let mut downloads: Vec<DownloadReport> = results
+            .iter()
+            .map(|r| DownloadReport {
+                url: r.url.clone(),
+                line_number: r.line_number,
+                tool: r.tool.clone(),
+                status: r.status().to_lowercase(),
+                exit_code: r.exit_code,
+                attempts: r.attempts,
+                duration_seconds: r.duration.as_secs_f64(),
+                bytes: r.bytes,
+                output_files: r.output_files.clone(),
+                error: (!r.success).then(|| r.error().map(String::from)).flatten(),
+                log_file: r.log_file.clone(),
+            })
+            .collect();
+        downloads.sort_by_key(|d| d.line_number);
+        let downloaded = results.iter().filter(|r| r.success).count();
+        let failed = results.iter().filter(|r| r.failed_download()).count();
+        RunReport {
+            version: crate::VERSION.to_string(),
+            tool_version,
+            args: std::env::args().collect(),
+            started: started.to_rfc3339(),
+            finished: finished.to_rfc3339(),
+            duration: (finished - started).to_std().unwrap_or_default(),
+            download_folder: download_folder.to_string(),
+            downloads,
+            move_outcome,
+            downloaded,
+            failed,
+            stopped: results.len() - downloaded - failed,
+            exit_code,
+        }
+    }
+
+    /// Write the report as JSON
+    /// # Parameters
+    /// path - The file to write to, it is replaced when it exists
+    /// # Returns
+    /// Nothing on ok, the error when the file could not be written.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, json + "\n")
+    }
+}
+
+fn as_seconds<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
@@ -0,0 +1,176 @@
+use crate::progress::Progress;
+use crate::render_duration_readable;
+use std::time::Duration;
+
+/// Number of lines of the standard error of the tool kept with a result
+pub const STDERR_TAIL_LINES: usize = 5;
+
+/// What the workers send to the main thread
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkerMessage {
+    /// The tool reported how far the download of an entry is
+    Progress {
+        line_number: usize,
+        url: String,
+        progress: Progress,
+    },
+    /// The download of an entry is over, whether it succeeded or not
+    Finished(JobResult),
+}
+
+/// The outcome of the download of one entry, the workers send these to the main thread.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobResult {
+    pub url: String,
+    /// Line number in the video list
+    pub line_number: usize,
+    /// Name of the backend that did the download
+    pub tool: String,
+    pub success: bool,
+    /// Exit code of the tool, None when it did not run or was killed by a signal
+    pub exit_code: Option<i32>,
+    /// Whether the tool was killed for running too long or going silent
+    pub timed_out: bool,
+    /// Whether the download was stopped by Ctrl-C, it is neither done nor failed and goes to the unfinished list
+    pub interrupted: bool,
+    /// Time spent in the tool, over all the attempts
+    pub duration: Duration,
+    /// Number of times the tool was run for the entry
+    pub attempts: u32,
+    /// The files the tool reported to have written, relative to the download folder
+    pub output_files: Vec<String>,
+    /// The size of the output files that were found on disk
+    pub bytes: u64,
+    /// The last lines of the standard error of the tool, or the reason the tool could not run
+    pub stderr_tail: Vec<String>,
+    /// The file with the output of the tool, over all the attempts
+    pub log_file: Option<String>,
+}
+
+impl JobResult {
+    /// A result for a download that did not get to run the tool
+    /// # Parameters
+    /// url - The url of the entry<br/>
+    /// line_number - The line number of the entry<br/>
+    /// tool - The name of the backend<br/>
+    /// reason - Why the download failed<br/>
+    /// # Returns
+    /// A failed result with the reason as error.
+    pub fn failed(url: &str, line_number: usize, tool: &str, reason: String) -> JobResult {
+        JobResult {
+            url: url.to_string(),
+            line_number,
+            tool: tool.to_string(),
+            success: false,
+            exit_code: None,
+            timed_out: false,
+            interrupted: false,
+            duration: Duration::ZERO,
+            attempts: 1,
+            output_files: Vec::new(),
+            bytes: 0,
+            stderr_tail: vec![reason],
+            log_file: None,
+        }
+    }
+
+    /// Whether the tool got to run, a failure before that is not worth a retry
+    pub fn tool_ran(&self) -> bool {
+        self.exit_code.is_some() || self.timed_out || !self.duration.is_zero()
+    }
+
+    /// The status as shown in the summary
+    pub fn status(&self) -> &'static str {
+        match (self.success, self.interrupted, self.timed_out) {
+            (true, _, _) => "OK",
+            (false, true, _) => "STOPPED",
+            (false, false, true) => "TIMEOUT",
+            (false, false, false) => "FAILED",
+        }
+    }
+
+    /// Whether the download failed, an interrupted download did not get the chance to finish so it does not count
+    pub fn failed_download(&self) -> bool {
+        !self.success && !self.interrupted
+    }
+
+    /// The most telling line about why the download failed, the last line of the standard error.
+    pub fn error(&self) -> Option<&str> {
+        self.stderr_tail.last().map(String::as_str)
+    }
+}
+
+/// The last non-empty lines of the standard error of a tool
+/// # Parameters
+/// stderr - The standard error text
+/// # Returns
+/// At most `STDERR_TAIL_LINES` lines, in the order they were written.
+pub fn stderr_tail(stderr: &str) -> Vec<String> {
+    let lines: Vec<&str> = stderr
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Render the results as a table, the successes first, then the failures with their error.
+/// # Parameters
+/// results - The results of the batch
+/// # Returns
+/// The lines of the table, ready to log.
+pub fn render_summary(results: &[JobResult]) -> Vec<String> {
+    let mut sorted: Vec<&JobResult> = results.iter().collect();
+    sorted.sort_by_key(|r| (!r.success, r.line_number));
+    let url_width = sorted.iter().map(|r| r.url.len()).max().unwrap_or(0).max(3);
+    let mut lines = vec![format!(
+        "{:<7} {:>5} {:>4} {:>5} {:>8}  {:<url_width$}  DETAIL",
+        "STATUS", "LINE", "EXIT", "TRIES", "TIME", "URL"
+    )];
+    for r in sorted {
+        let exit = r
+            .exit_code
+            .map(|code| code.to_string())
+            .unwrap_or_else(|| String::from("-"));
+        let detail = match (r.success, &r.log_file) {
+            (true, _) => r.output_files.join(", "),
+            (false, Some(log_file)) => {
+                format!("{} (see {})", r.error().unwrap_or_default(), log_file)
+            }
+            (false, None) => r.error().unwrap_or_default().to_string(),
+        };
+        lines.push(format!(
+            "{:<7} {:>5} {:>4} {:>5} {:>8}  {:<url_width$}  {}",
+            r.status(),
+            r.line_number,
+            exit,
+            r.attempts,
+            render_duration_readable(chrono::Duration::from_std(r.duration).unwrap_or_default()),
+            r.url,
+            detail
+        ));
+    }
+    let downloaded = results.iter().filter(|r| r.success).count();
+    let failed = results.iter().filter(|r| r.failed_download()).count();
+    let stopped = results.len() - downloaded - failed;
+    lines.push(if stopped > 0 {
+        format!(
+            "{} downloaded, {} failed, {} stopped, {} in total",
+            downloaded,
+            failed,
+            stopped,
+            results.len()
+        )
+    } else {
+        format!(
+            "{} downloaded, {} failed, {} in total",
+            downloaded,
+            failed,
+            results.len()
+        )
+    });
+    lines
+}
@@ -0,0 +1,114 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Errors of the tool that are worth another try, checked before the permanent ones as a rate limit can come with a
+/// message that reads like the video is gone.
+const RETRYABLE_ERRORS: [&str; 14] = [
+    "http error 429",
+    "too many requests",
+    "try again later",
+    "rate-limit",
+    "rate limit",
+    "timed out",
+    "timeout",
+    "connection reset",
+    "connection refused",
+    "temporary failure in name resolution",
+    "network is unreachable",
+    "incompleteread",
+    "http error 5",
+    "unable to download webpage",
+];
+
+/// HTTP errors that say the page is gone, checked before anything else as yt-dlp wraps them in a message like
+/// `Unable to download webpage` that also comes with the errors worth another try.
+const GONE_ERRORS: [&str; 2] = ["http error 404", "http error 410"];
+
+/// Errors of the tool that will not go away by trying again.
+const PERMANENT_ERRORS: [&str; 14] = [
+    "private video",
+    "video is private",
+    "video unavailable",
+    "has been removed",
+    "no longer available",
+    "account associated with this video has been terminated",
+    "available in your country",
+    "blocked it in your country",
+    "geo restrict",
+    "sign in to confirm your age",
+    "members-only",
+    "join this channel",
+    "unsupported url",
+    "copyright",
+];
+
+/// What kind of failure the tool reported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailureKind {
+    /// Rate limits, timeouts and network trouble, likely to work on a next try
+    Retryable,
+    /// The video is private, removed or blocked, trying again will not help
+    Permanent,
+    /// Nothing recognised, it gets another try
+    Unknown,
+}
+
+impl FailureKind {
+    pub fn is_retryable(&self) -> bool {
+        *self != FailureKind::Permanent
+    }
+}
+
+/// Classify the failure of a download by the standard error of the tool.
+/// # Parameters
+/// stderr - The (tail of the) standard error of the tool
+/// # Returns
+/// The kind of failure.
+pub fn classify_failure(stderr: &str) -> FailureKind {
+    let stderr = stderr.to_lowercase();
+    if GONE_ERRORS.iter().any(|e| stderr.contains(e)) {
+        FailureKind::Permanent
+    } else if RETRYABLE_ERRORS.iter().any(|e| stderr.contains(e)) {
+        FailureKind::Retryable
+    } else if PERMANENT_ERRORS.iter().any(|e| stderr.contains(e)) {
+        FailureKind::Permanent
+    } else {
+        FailureKind::Unknown
+    }
+}
+
+/// How often and how long apart a failed download is tried again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of tries in total, 1 means no retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, it doubles for every retry after that
+    pub initial_delay: Duration,
+    /// The delay never grows past this
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// The delay before the next try, exponential backoff with jitter so the workers that hit a rate limit at the
+    /// same time do not all come back at the same time. The delay is between half and the full backoff.
+    /// # Parameters
+    /// attempt - The try that just failed, starting at 1
+    /// # Returns
+    /// The time to wait before trying again.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let jitter = random_fraction();
+        backoff.mul_f64(0.5 + jitter / 2.0)
+    }
+}
+
+/// A random number from 0 up to 1, the hasher of the standard library is seeded randomly so that saves a
+/// dependency for the little randomness we need.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,225 @@
+use crate::shutdown::Shutdown;
+use log::{trace, warn};
+use std::io::{self, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the running tool is checked for having exited, timed out or stalled
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Limits on how long a tool may run, None means no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TimeLimits {
+    /// Wall clock time the tool may run in total
+    pub timeout: Option<Duration>,
+    /// Time the tool may go without writing anything to its standard output
+    pub stall_timeout: Option<Duration>,
+}
+
+/// Why a tool was killed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KillReason {
+    TimedOut(Duration),
+    Stalled(Duration),
+    /// Ctrl-C was pressed twice
+    Interrupted,
+}
+
+impl KillReason {
+    /// A line telling why the tool was killed, it reads as a timeout for the retry classifier.
+    pub fn message(&self) -> String {
+        match self {
+            KillReason::TimedOut(limit) => {
+                format!("Killed, timed out after {} seconds", limit.as_secs())
+            }
+            KillReason::Stalled(limit) => format!(
+                "Killed, timed out with no output for {} seconds",
+                limit.as_secs()
+            ),
+            KillReason::Interrupted => String::from("Killed, the batch was interrupted"),
+        }
+    }
+}
+
+/// What a tool did, like `std::process::Output` but with the reason it was killed, if it was.
+pub struct ToolOutput {
+    /// The exit status, None when the tool was killed
+    pub status: Option<ExitStatus>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub killed: Option<KillReason>,
+}
+
+/// Run a tool to the end, or kill it when it runs past its time limits.
+/// # Parameters
+/// command - The command to run, its standard output and error are captured<br/>
+/// limits - How long the tool may run<br/>
+/// shutdown - The tool is killed when the user asks to stop the running downloads<br/>
+/// on_line - Called with every line of the standard output while the tool runs, on the thread that runs the tool<br/>
+/// # Returns
+/// The output of the tool, or the error when it could not be started.
+pub fn run_tool(
+    command: &mut Command,
+    limits: &TimeLimits,
+    shutdown: &Shutdown,
+    on_line: &mut dyn FnMut(&str),
+) -> io::Result<ToolOutput> {
+    // The tool gets its own process group, so a Ctrl-C in the terminal only reaches us and we get to decide whether
+    // the running downloads are allowed to finish. It also lets us kill the tool together with its ffmpeg children.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // On the list until this call returns, so a third Ctrl-C can kill it before leaving.
+    let _tracked = shutdown.track(child.id());
+    let started = Instant::now();
+    let last_output = Arc::new(Mutex::new(started));
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let stderr = Arc::new(Mutex::new(Vec::new()));
+    // The lines of the standard output come to this thread over a channel, that way the caller does not need to
+    // hand out a callback that outlives this call to the reader threads.
+    let (line_tx, line_rx) = channel::<String>();
+    let stdout_reader = child.stdout.take().map(|pipe| {
+        spawn_reader(
+            pipe,
+            Arc::clone(&stdout),
+            Some((Arc::clone(&last_output), line_tx)),
+        )
+    });
+    let stderr_reader = child
+        .stderr
+        .take()
+        .map(|pipe| spawn_reader(pipe, Arc::clone(&stderr), None));
+
+    let mut killed = None;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        let now = Instant::now();
+        let silent_for = now.duration_since(*last_output.lock().expect("Output lock poisoned"));
+        killed = match (limits.timeout, limits.stall_timeout) {
+            _ if shutdown.kill_requested() => Some(KillReason::Interrupted),
+            (Some(limit), _) if now.duration_since(started) > limit => {
+                Some(KillReason::TimedOut(limit))
+            }
+            (_, Some(limit)) if silent_for > limit => Some(KillReason::Stalled(limit)),
+            _ => None,
+        };
+        if let Some(reason) = killed {
+            warn!("{}, pid {}", reason.message(), child.id());
+            kill(&mut child);
+            break None;
+        }
+        wait_for_lines(&line_rx, on_line);
+    };
+    // The readers stop when the pipes close, that is when the tool exits. A killed tool can leave children behind
+    // that keep the pipes open (ffmpeg), so we do not wait for the readers then and take what they have read.
+    if killed.is_none() {
+        for reader in [stdout_reader, stderr_reader].into_iter().flatten() {
+            let _ = reader.join();
+        }
+    }
+    for line in line_rx.try_iter() {
+        on_line(&line);
+    }
+    let stdout = stdout.lock().expect("Output lock poisoned").clone();
+    let stderr = stderr.lock().expect("Output lock poisoned").clone();
+    Ok(ToolOutput {
+        status,
+        stdout,
+        stderr,
+        killed,
+    })
+}
+
+/// Kill the tool and reap it so it does not linger as a zombie
+fn kill(child: &mut Child) {
+    // Kill the whole process group first, that takes the children the tool started (ffmpeg) down with it.
+    #[cfg(unix)]
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: kill has no memory safety requirements, the group is the one the tool was started in.
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+    if let Err(e) = child.kill() {
+        warn!("Could not kill pid {}: {}", child.id(), e);
+    }
+    let _ = child.wait();
+}
+
+/// Hand the lines that came in to the caller, waiting for the next line at most one poll interval.
+fn wait_for_lines(line_rx: &Receiver<String>, on_line: &mut dyn FnMut(&str)) {
+    match line_rx.recv_timeout(POLL_INTERVAL) {
+        Ok(line) => {
+            on_line(&line);
+            for line in line_rx.try_iter() {
+                on_line(&line);
+            }
+        }
+        Err(RecvTimeoutError::Timeout) => {}
+        // The tool closed its output but has not exited yet, there is nothing to wait for but the exit.
+        Err(RecvTimeoutError::Disconnected) => thread::sleep(POLL_INTERVAL),
+    }
+}
+
+/// Read a pipe of the tool into the buffer until it closes. For the standard output the time of the last output is
+/// noted and the lines are sent out as they come in, split on new lines and carriage returns.
+fn spawn_reader(
+    mut pipe: impl Read + Send + 'static,
+    buffer: Arc<Mutex<Vec<u8>>>,
+    follow: Option<(Arc<Mutex<Instant>>, Sender<String>)>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut chunk = [0u8; 8192];
+        let mut partial_line: Vec<u8> = Vec::new();
+        loop {
+            match pipe.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    buffer
+                        .lock()
+                        .expect("Output lock poisoned")
+                        .extend_from_slice(&chunk[..n]);
+                    if let Some((last_output, line_tx)) = &follow {
+                        *last_output.lock().expect("Output lock poisoned") = Instant::now();
+                        for &byte in &chunk[..n] {
+                            if byte == b'\n' || byte == b'\r' {
+                                send_line(line_tx, &mut partial_line);
+                            } else {
+                                partial_line.push(byte);
+                            }
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    trace!("Stopped reading the tool output: {}", e);
+                    break;
+                }
+            }
+        }
+        if let Some((_, line_tx)) = &follow {
+            send_line(line_tx, &mut partial_line);
+        }
+    })
+}
+
+/// Send the line that has been collected, when it is not empty, and start a new one
+fn send_line(line_tx: &Sender<String>, line: &mut Vec<u8>) {
+    if !line.is_empty() {
+        // The receiver is gone when the tool was killed and we stopped listening, the line can go then.
+        let _ = line_tx.send(String::from_utf8_lossy(line).into_owned());
+        line.clear();
+    }
+}
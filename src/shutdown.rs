@@ -0,0 +1,130 @@
+use log::{error, warn};
+use std::collections::BTreeSet;
+use std::process::exit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Exit code when the batch was stopped by Ctrl-C or SIGTERM, the usual 128 + SIGINT
+pub const INTERRUPTED_EXIT_CODE: i32 = 0x0082;
+
+/// Counts the Ctrl-C / SIGTERM signals. The first one stops new downloads from starting, the second one stops the
+/// running downloads as well, a third one kills the running tools and leaves right away.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    signals: Arc<AtomicUsize>,
+    /// The pids of the running tools, each one leads its own process group
+    children: Arc<Mutex<BTreeSet<u32>>>,
+}
+
+/// A running tool in the list of the shutdown, it is taken off the list when this is dropped.
+pub struct TrackedChild {
+    shutdown: Shutdown,
+    pid: u32,
+}
+
+impl Drop for TrackedChild {
+    fn drop(&mut self) {
+        self.shutdown
+            .children
+            .lock()
+            .expect("Children lock poisoned")
+            .remove(&self.pid);
+    }
+}
+
+impl Shutdown {
+    /// Install the signal handler, it can only be installed once per process.
+    /// # Returns
+    /// The shutdown state the handler updates, or the error when the handler could not be installed.
+    pub fn install() -> Result<Shutdown, ctrlc::Error> {
+        let shutdown = Shutdown::default();
+        let handler_shutdown = shutdown.clone();
+        ctrlc::set_handler(move || match handler_shutdown.request() {
+            1 => warn!(
+                "Stopping after the running downloads, the finished ones will be moved. Press Ctrl-C again to stop the running downloads"
+            ),
+            2 => warn!("Stopping the running downloads, the finished ones will be moved"),
+            _ => {
+                error!("Leaving right away");
+                // The tools run in their own process group, the Ctrl-C of the terminal does not reach them.
+                handler_shutdown.kill_children();
+                exit(INTERRUPTED_EXIT_CODE);
+            }
+        })?;
+        Ok(shutdown)
+    }
+
+    /// Count a signal
+    /// # Returns
+    /// The number of signals so far.
+    pub fn request(&self) -> usize {
+        self.signals.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Put a running tool on the list of the tools to kill when leaving right away
+    /// # Parameters
+    /// pid - The pid of the tool, it leads its own process group
+    /// # Returns
+    /// The entry on the list, the tool is taken off the list when it is dropped.
+    pub fn track(&self, pid: u32) -> TrackedChild {
+        self.children
+            .lock()
+            .expect("Children lock poisoned")
+            .insert(pid);
+        TrackedChild {
+            shutdown: self.clone(),
+            pid,
+        }
+    }
+
+    /// Kill the process groups of the running tools, the children they started (ffmpeg) go with them.
+    pub fn kill_children(&self) {
+        // The handler can run while a worker holds the lock and panicked, the pids are still good then.
+        let children = match self.children.lock() {
+            Ok(children) => children.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        for pid in children {
+            warn!("Killing pid {}", pid);
+            #[cfg(unix)]
+            if let Ok(pid) = libc::pid_t::try_from(pid) {
+                // SAFETY: kill has no memory safety requirements, the group is the one the tool was started in.
+                unsafe {
+                    libc::kill(-pid, libc::SIGKILL);
+                }
+            }
+        }
+    }
+
+    /// Whether no new downloads should be started
+    pub fn stop_requested(&self) -> bool {
+        self.signals.load(Ordering::SeqCst) >= 1
+    }
+
+    /// Whether the running downloads should be killed
+    pub fn kill_requested(&self) -> bool {
+        self.signals.load(Ordering::SeqCst) >= 2
+    }
+
+    /// Sleep, but wake up early when a stop is requested
+    /// # Parameters
+    /// duration - How long to sleep
+    /// # Returns
+    /// True when the sleep was cut short by a stop request.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let until = Instant::now() + duration;
+        while Instant::now() < until {
+            if self.stop_requested() {
+                return true;
+            }
+            thread::sleep(
+                until
+                    .saturating_duration_since(Instant::now())
+                    .min(Duration::from_millis(100)),
+            );
+        }
+        self.stop_requested()
+    }
+}
@@ -0,0 +1,182 @@
+use crate::results::JobResult;
+use crate::videolist::VideoEntry;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the batch state file, it lives in the download folder next to the downloads.
+pub const STATE_FILE_NAME: &str = ".yt-parallel-state.toml";
+
+/// Where a line of the video list is in the batch
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryState {
+    /// Not started yet
+    Pending,
+    /// A worker is downloading it, after a crash this means it was cut off halfway
+    Running,
+    Done,
+    Failed,
+}
+
+/// The state of one line of the video list
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct EntryRecord {
+    pub line_number: usize,
+    /// The entry as a line of the video list, with its options
+    pub line: String,
+    pub state: EntryState,
+    /// Number of times the tool was run for the entry, over all the runs of the batch
+    pub attempts: u32,
+    pub output_files: Vec<String>,
+}
+
+/// What is written to the state file
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+struct StateFile {
+    entries: Vec<EntryRecord>,
+}
+
+/// The state of a batch, it is written to the state file on every change so a batch that is cut off by a crash or
+/// a reboot can be resumed with `--resume`.
+pub struct BatchState {
+    path: PathBuf,
+    records: Mutex<BTreeMap<String, EntryRecord>>,
+}
+
+impl BatchState {
+    /// Load the state file of an earlier run of the batch.
+    /// # Parameters
+    /// folder_name - The download folder of the batch
+    /// # Returns
+    /// The state, or the error when there is no state file or it cannot be read.
+    pub fn load(folder_name: &str) -> Result<BatchState, String> {
+        let path = Path::new(folder_name).join(STATE_FILE_NAME);
+        // Without the file there is no batch to go on with, a typo in the folder would download the whole list again.
+        if !path.is_file() {
+            return Err(format!(
+                "There is no batch to resume in {}, it has no state file {}",
+                folder_name, STATE_FILE_NAME
+            ));
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read the state file {}: {e}", path.display()))?;
+        let state: StateFile = toml::from_str(&content).map_err(|e| {
+            format!(
+                "State file {} is not valid: {}",
+                path.display(),
+                e.message()
+            )
+        })?;
+        let mut records = BTreeMap::new();
+        for record in state.entries {
+            records.insert(record.line.clone(), record);
+        }
+        info!(
+            "Loaded the state of {} entries from {}",
+            records.len(),
+            path.display()
+        );
+        Ok(BatchState {
+            path,
+            records: Mutex::new(records),
+        })
+    }
+
+    /// A state without an earlier run
+    /// # Parameters
+    /// folder_name - The download folder of the batch
+    pub fn new(folder_name: &str) -> BatchState {
+        BatchState {
+            path: Path::new(folder_name).join(STATE_FILE_NAME),
+            records: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Whether the entry was downloaded in an earlier run of the batch
+    pub fn is_done(&self, entry: &VideoEntry) -> bool {
+        self.records
+            .lock()
+            .expect("State lock poisoned")
+            .get(&entry.to_line())
+            .is_some_and(|record| record.state == EntryState::Done)
+    }
+
+    /// Add the entries of this run as pending, keeping the attempts of an earlier run, and write the file.
+    /// # Parameters
+    /// entries - The entries that will be downloaded
+    /// # Returns
+    /// Nothing on ok, the error when the state file could not be written.
+    pub fn start(&self, entries: &[VideoEntry]) -> io::Result<()> {
+        let mut records = self.records.lock().expect("State lock poisoned");
+        for entry in entries {
+            let line = entry.to_line();
+            let attempts = records.get(&line).map_or(0, |record| record.attempts);
+            records.insert(
+                line.clone(),
+                EntryRecord {
+                    line_number: entry.line_number,
+                    line,
+                    state: EntryState::Pending,
+                    attempts,
+                    output_files: Vec::new(),
+                },
+            );
+        }
+        self.write(&records)
+    }
+
+    /// Mark an entry as being downloaded
+    pub fn running(&self, entry: &VideoEntry) -> io::Result<()> {
+        self.update(entry, |record| record.state = EntryState::Running)
+    }
+
+    /// Record the result of an entry, an interrupted download goes back to pending.
+    pub fn finished(&self, entry: &VideoEntry, result: &JobResult) -> io::Result<()> {
+        self.update(entry, |record| {
+            record.state = if result.success {
+                EntryState::Done
+            } else if result.interrupted {
+                EntryState::Pending
+            } else {
+                EntryState::Failed
+            };
+            record.attempts += result.attempts;
+            record.output_files = result.output_files.clone();
+        })
+    }
+
+    /// Remove the state file, done when the batch has run to the end.
+    pub fn remove(&self) -> io::Result<()> {
+        if self.path.exists() {
+            debug!("Removing the state file {}", self.path.display());
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+
+    fn update(&self, entry: &VideoEntry, change: impl FnOnce(&mut EntryRecord)) -> io::Result<()> {
+        // Hold the lock while writing so the workers do not write the file at the same time.
+        let mut records = self.records.lock().expect("State lock poisoned");
+        if let Some(record) = records.get_mut(&entry.to_line()) {
+            change(record);
+        }
+        self.write(&records)
+    }
+
+    /// Write the state to a temporary file and move it in place, so a crash halfway never leaves half a file.
+    fn write(&self, records: &BTreeMap<String, EntryRecord>) -> io::Result<()> {
+        let mut entries: Vec<EntryRecord> = records.values().cloned().collect();
+        entries.sort_by_key(|record| record.line_number);
+        let content = toml::to_string(&StateFile { entries }).map_err(io::Error::other)?;
+        let temporary = self.path.with_extension("toml.tmp");
+        fs::write(&temporary, content)?;
+        fs::rename(&temporary, &self.path)
+    }
+}
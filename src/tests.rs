@@ -1,11 +1,39 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
-    use crate::logging::initialize_logging;
+    use crate::archive::{Archive, archive_key_for_url};
+    use crate::config::{ConfigLayer, DEFAULT_PROFILE, env_layers, merge_layers, parse_config};
+    use crate::display::{render_progress, render_size};
+    use crate::downloader::{Downloader, GenericCommand, YT_DLP_DEFAULT_ARGS, YtDlp, host_matches};
+    use crate::joblog::{append_job_log, job_log_path};
+    use crate::logfile::{RotatingFile, Rotation, parse_rotation};
+    use crate::logging::{
+        JobContext, LogFormat, initialize_logging, json_log_line, set_job_attempt, set_job_context,
+    };
+    use crate::merge::{ConflictPolicy, MergeAction, merge_folder};
+    use crate::progress::{Phase, Progress, parse_size, parse_yt_dlp_progress};
+    use crate::report::{MoveOutcome, RunReport};
+    use crate::results::{JobResult, STDERR_TAIL_LINES, render_summary, stderr_tail};
+    use crate::retry::{FailureKind, RetryPolicy, classify_failure};
+    use crate::runner::{KillReason, TimeLimits, run_tool};
+    use crate::shutdown::Shutdown;
+    use crate::state::{BatchState, STATE_FILE_NAME};
+    use crate::videolist::{
+        VideoEntry, canonical_url, dedupe_entries, normalize_url, parse_line, read_video_list,
+        write_video_list,
+    };
+    use crate::workdir::{
+        CollisionPolicy, FolderTokens, create_download_folder, render_folder_name, resumable_folder,
+    };
     use crate::{
-        check_downloader_present, evaluate_move_path, move_to_nas, render_duration_readable,
+        Args, check_downloader_present, default_jobs, evaluate_move_path, format_date_pattern,
+        move_to_nas, render_duration_readable, resolve_downloader, tool_version,
     };
-    use chrono::Duration;
+    use chrono::{Duration, TimeZone};
+    use clap::CommandFactory;
+    use std::collections::BTreeMap;
     use std::fs;
+    use std::path::{Path, PathBuf};
 
     #[test]
     fn app_present() {
@@ -18,6 +46,23 @@ mod tests {
         let result = check_downloader_present("IAmNotThere".to_string());
         assert!(!result);
     }
+    #[test]
+    fn resolve_downloader_by_full_path() {
+        let in_path = resolve_downloader("yt-dlp").expect("yt-dlp should be in the PATH");
+        let by_full_path = resolve_downloader(&in_path.display().to_string());
+        assert_eq!(Some(in_path), by_full_path);
+    }
+
+    #[test]
+    fn resolve_downloader_not_present() {
+        assert_eq!(None, resolve_downloader("/IAmNotThere/yt-dlp"));
+    }
+
+    #[test]
+    fn default_jobs_is_at_least_one() {
+        assert!(default_jobs() >= 1);
+    }
+
     #[test]
     fn move_tester() {
         use std::fs::File;
@@ -25,13 +70,24 @@ mod tests {
         let source_dir: String = "test".to_string();
         let source_file: String = "text.txt".to_string();
         let target_dir: String = "test_target".to_string();
-        initialize_logging("trace".to_string());
+        initialize_logging(
+            "trace".to_string(),
+            LogFormat::Text,
+            &indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+            None,
+        )
+        .expect("Console logging should set up");
         //Setup
         fs::create_dir(source_dir.clone()).expect("Creation source folder failed");
         File::create(format!("{source_dir}/{source_file}")).expect("Could not create test file");
         //Move
 
-        move_to_nas(source_dir.clone(), target_dir.clone());
+        move_to_nas(
+            source_dir.clone(),
+            target_dir.clone(),
+            CollisionPolicy::Fail,
+            ConflictPolicy::Skip,
+        );
 
         //Check if the move has succeeded
         let dir_exists = Path::new(&target_dir).exists();
@@ -50,25 +106,228 @@ mod tests {
     #[test]
     fn move_path_evaluation_pass_path() {
         let test_target = "/usr/local/bin/test";
-        let os_type = "windows";
-        let result_path = evaluate_move_path(os_type, &test_target.to_string());
-        assert_eq!(test_target, result_path);
+        let result_path = evaluate_move_path(Some(test_target));
+        assert_eq!(Some(test_target.to_string()), result_path);
     }
 
     #[test]
-    fn move_path_evaluation_no_path_os_windows() {
-        let test_target = "";
-        let os_type = "windows";
-        let result_path = evaluate_move_path(os_type, &test_target.to_string());
-        assert_eq!("H:/media/youtube/", result_path);
+    fn move_path_evaluation_no_path() {
+        assert_eq!(None, evaluate_move_path(Some("")));
+        assert_eq!(None, evaluate_move_path(None));
     }
 
     #[test]
-    fn move_path_evaluation_no_path_os_macos() {
-        let test_target = "";
-        let os_type = "macos";
-        let result_path = evaluate_move_path(os_type, &test_target.to_string());
-        assert_eq!("/Volumes/huge/media/youtube/", result_path);
+    fn download_folder_collisions() {
+        let folder = Path::new("test_collision_work/20240102");
+        fs::create_dir_all(folder).expect("Could not create the folder");
+        let suffixed = create_download_folder(folder, CollisionPolicy::Suffix);
+        let merged = create_download_folder(folder, CollisionPolicy::Merge);
+        let failed = create_download_folder(folder, CollisionPolicy::Fail);
+        fs::remove_dir_all("test_collision_work").expect("Could not remove the folder");
+        assert_eq!(
+            Ok(PathBuf::from("test_collision_work/20240102-2")),
+            suffixed
+        );
+        assert_eq!(Ok(folder.to_path_buf()), merged);
+        assert!(failed.is_err());
+    }
+
+    #[test]
+    fn bare_resume_finds_the_newest_batch() {
+        let work = Path::new("test_resume_work");
+        let folder = work.join("20240102");
+        assert!(resumable_folder(&folder).is_err());
+        for batch in [
+            "20240102",
+            "20240102-2",
+            "20240102-3",
+            "20240102-10",
+            "20240102-x",
+        ] {
+            fs::create_dir_all(work.join(batch)).expect("Could not create the batch");
+        }
+        // Only the folders with a state file are batches that can be resumed
+        for batch in ["20240102", "20240102-2", "20240102-x"] {
+            fs::write(work.join(batch).join(STATE_FILE_NAME), "").expect("Could not write state");
+        }
+        let newest = resumable_folder(&folder);
+        fs::write(work.join("20240102-10").join(STATE_FILE_NAME), "")
+            .expect("Could not write state");
+        let after_ten = resumable_folder(&folder);
+        fs::remove_dir_all(work).expect("Could not remove the folder");
+        assert_eq!(Ok(work.join("20240102-2")), newest);
+        assert_eq!(Ok(work.join("20240102-10")), after_ten);
+    }
+
+    #[test]
+    fn move_target_collisions() {
+        let target = "test_collision_nas";
+        let make_batch = |content: &str| {
+            fs::create_dir_all("test_collision_batch/pods").expect("Could not create the batch");
+            fs::write("test_collision_batch/a.mp4", content).expect("Could not write a");
+            fs::write("test_collision_batch/pods/b.mp3", content).expect("Could not write b");
+        };
+        make_batch("first");
+        let (first, _) = move_to_nas(
+            "test_collision_batch".into(),
+            target.into(),
+            CollisionPolicy::Fail,
+            ConflictPolicy::Skip,
+        );
+        make_batch("second");
+        let (failed, _) = move_to_nas(
+            "test_collision_batch".into(),
+            target.into(),
+            CollisionPolicy::Fail,
+            ConflictPolicy::Skip,
+        );
+        let (suffixed, _) = move_to_nas(
+            "test_collision_batch".into(),
+            target.into(),
+            CollisionPolicy::Suffix,
+            ConflictPolicy::Skip,
+        );
+        make_batch("third");
+        fs::remove_file("test_collision_batch/a.mp4").expect("Could not remove a");
+        fs::write("test_collision_batch/c.mp4", "third").expect("Could not write c");
+        fs::remove_file(format!("{target}/test_collision_batch/pods/b.mp3"))
+            .expect("Could not remove b");
+        let (merged, _) = move_to_nas(
+            "test_collision_batch".into(),
+            target.into(),
+            CollisionPolicy::Merge,
+            ConflictPolicy::Skip,
+        );
+        let read = |file: &str| fs::read_to_string(format!("{target}/{file}")).unwrap_or_default();
+        let results = (
+            read("test_collision_batch/a.mp4"),
+            read("test_collision_batch-2/pods/b.mp3"),
+            read("test_collision_batch/pods/b.mp3"),
+            read("test_collision_batch/c.mp4"),
+            Path::new("test_collision_batch").exists(),
+        );
+        // Files that are skipped stay behind, so the move did not succeed
+        make_batch("fourth");
+        let (partly, decisions) = move_to_nas(
+            "test_collision_batch".into(),
+            target.into(),
+            CollisionPolicy::Merge,
+            ConflictPolicy::Skip,
+        );
+        let left_behind = Path::new("test_collision_batch/a.mp4").exists();
+        fs::remove_dir_all("test_collision_batch").expect("Could not remove the batch");
+        fs::remove_dir_all(target).expect("Could not remove the target");
+        assert!(first && !failed && suffixed && merged);
+        assert!(!partly && left_behind);
+        assert!(decisions.iter().all(|d| d.action == MergeAction::Skipped));
+        assert_eq!(2, decisions.len());
+        assert_eq!(
+            (
+                "first".to_string(),
+                "second".to_string(),
+                "third".to_string(),
+                "third".to_string(),
+                false
+            ),
+            results
+        );
+    }
+
+    #[test]
+    fn merge_goes_on_after_a_failed_file() {
+        let (source, target) = (
+            Path::new("test_merge_fail_work"),
+            Path::new("test_merge_fail_nas"),
+        );
+        fs::create_dir_all(source.join("pods")).expect("Could not create the source");
+        fs::create_dir_all(target).expect("Could not create the target");
+        fs::write(source.join("a.mp4"), "a").expect("Could not write a");
+        fs::write(source.join("pods/b.mp3"), "b").expect("Could not write b");
+        fs::write(source.join("z.mp4"), "z").expect("Could not write z");
+        // A file where the merge needs a folder, b.mp3 cannot go in
+        fs::write(target.join("pods"), "not a folder").expect("Could not write pods");
+        let decisions = merge_folder(source, target, ConflictPolicy::Skip);
+        let left_behind = source.join("pods/b.mp3").exists();
+        let moved = target.join("z.mp4").exists();
+        fs::remove_dir_all(target).expect("Could not remove the target");
+        fs::remove_dir_all(source).expect("Could not remove the source");
+        let decisions = decisions.expect("Merge should go on");
+        assert_eq!(
+            vec![MergeAction::Moved, MergeAction::Failed, MergeAction::Moved],
+            decisions.iter().map(|d| d.action).collect::<Vec<_>>()
+        );
+        assert!(decisions[1].error.is_some());
+        assert!(left_behind && moved);
+    }
+
+    #[test]
+    fn merge_conflict_policies() {
+        let mut actions = Vec::new();
+        for policy in [
+            ConflictPolicy::Skip,
+            ConflictPolicy::Overwrite,
+            ConflictPolicy::KeepBoth,
+            ConflictPolicy::OverwriteIfLarger,
+        ] {
+            let (source, target) = (Path::new("test_merge_work"), Path::new("test_merge_nas"));
+            fs::create_dir_all(source.join("pods")).expect("Could not create the source");
+            fs::create_dir_all(target).expect("Could not create the target");
+            fs::write(source.join("new.mp4"), "new").expect("Could not write new");
+            fs::write(source.join("pods/smaller.mp3"), "1").expect("Could not write smaller");
+            fs::write(source.join("same.mp4"), "larger").expect("Could not write same");
+            fs::create_dir_all(target.join("pods")).expect("Could not create the target pods");
+            fs::write(target.join("pods/smaller.mp3"), "12").expect("Could not write smaller");
+            fs::write(target.join("same.mp4"), "old").expect("Could not write same");
+            let decisions = merge_folder(source, target, policy).expect("Merge should work");
+            let kept = (
+                fs::read_to_string(target.join("same.mp4")).unwrap_or_default(),
+                fs::read_to_string(target.join("same-2.mp4")).unwrap_or_default(),
+                source.exists(),
+            );
+            fs::remove_dir_all(target).expect("Could not remove the target");
+            let _ = fs::remove_dir_all(source);
+            assert_eq!("new.mp4", decisions[0].file);
+            assert_eq!(Some(3), decisions[2].existing_bytes);
+            actions.push((decisions.iter().map(|d| d.action).collect::<Vec<_>>(), kept));
+        }
+        let owned = |a: &str, b: &str, left| (a.to_string(), b.to_string(), left);
+        assert_eq!(
+            vec![
+                (
+                    vec![
+                        MergeAction::Moved,
+                        MergeAction::Skipped,
+                        MergeAction::Skipped
+                    ],
+                    owned("old", "", true)
+                ),
+                (
+                    vec![
+                        MergeAction::Moved,
+                        MergeAction::Overwritten,
+                        MergeAction::Overwritten
+                    ],
+                    owned("larger", "", false)
+                ),
+                (
+                    vec![
+                        MergeAction::Moved,
+                        MergeAction::KeptBoth,
+                        MergeAction::KeptBoth
+                    ],
+                    owned("old", "larger", false)
+                ),
+                (
+                    vec![
+                        MergeAction::Moved,
+                        MergeAction::Skipped,
+                        MergeAction::Overwritten
+                    ],
+                    owned("larger", "", true)
+                ),
+            ],
+            actions
+        );
     }
 
     #[ignore]
@@ -87,7 +346,7 @@ mod tests {
         match create_dir_result {
             Ok(_) => {}
             Err(e) => {
-                println!("Create folder failed, may already exist: {}", e.to_string())
+                println!("Create folder failed, may already exist: {}", e)
             }
         }
         //Put a file in the source dir
@@ -97,7 +356,7 @@ mod tests {
             Err(e) => {
                 println!(
                     "Create file failed, continue as it may already exist: {}",
-                    e.to_string()
+                    e
                 )
             }
         }
@@ -110,7 +369,7 @@ mod tests {
             println!("Source {} exists ", source_dir.clone());
         } else {
             println!("Source {} does not exist ", source_dir.clone());
-            assert!(false);
+            panic!("Source {} does not exist", source_dir);
         }
         let demo_target = Path::new(&target_dir);
         if !demo_target.exists() {
@@ -121,7 +380,7 @@ mod tests {
             Ok(_) => {}
             Err(e) => {
                 fs::remove_dir_all(source_dir.clone()).expect("Could note remove the source dir");
-                assert!(false, "Move failed {e}")
+                panic!("Move failed {e}")
             }
         }
 
@@ -146,7 +405,7 @@ mod tests {
         match create_dir {
             Ok(_) => {}
             Err(e) => {
-                println!("Did already exist? We push on: {}", e.to_string())
+                println!("Did already exist? We push on: {}", e)
             }
         }
         //Put a file in the source dir
@@ -164,7 +423,7 @@ mod tests {
                     source_dir.clone(),
                     name
                 );
-                fs::remove_file(Path::new(&format!("{}", name))).unwrap();
+                fs::remove_file(Path::new(&name)).unwrap();
             }
         }
         let part_file_removed =
@@ -184,14 +443,1046 @@ mod tests {
     #[test]
     fn test_one_minute_one_second() {
         let mut duration = Duration::minutes(1);
-        duration = duration + Duration::seconds(1);
+        duration += Duration::seconds(1);
         assert_eq!(render_duration_readable(duration), "00:01:01");
     }
 
     #[test]
     fn test_one_minute_61_seconds() {
         let mut duration = Duration::minutes(1);
-        duration = duration + Duration::seconds(61);
+        duration += Duration::seconds(61);
         assert_eq!(render_duration_readable(duration), "00:02:01");
     }
+
+    #[test]
+    fn yt_dlp_output_keeps_merged_file_only() {
+        let stdout = "[youtube] Extracting URL: https://youtu.be/abc\n\
+            [download] Destination: clip.f137.mp4\n\
+            [download] 100% of 10.00MiB in 00:00:01\n\
+            [download] Destination: clip.f140.m4a\n\
+            [Merger] Merging formats into \"clip.mp4\"\n";
+        let downloader = YtDlp::new(
+            PathBuf::from("yt-dlp"),
+            BTreeMap::new(),
+            DEFAULT_PROFILE.to_string(),
+        );
+        assert_eq!(
+            vec!["clip.mp4".to_string()],
+            downloader.parse_output(stdout)
+        );
+    }
+
+    #[test]
+    fn yt_dlp_output_already_downloaded() {
+        let stdout = "[download] clip.mp4 has already been downloaded\n";
+        let downloader = YtDlp::new(
+            PathBuf::from("yt-dlp"),
+            BTreeMap::new(),
+            DEFAULT_PROFILE.to_string(),
+        );
+        assert_eq!(
+            vec!["clip.mp4".to_string()],
+            downloader.parse_output(stdout)
+        );
+    }
+
+    #[test]
+    fn host_matching_includes_subdomains() {
+        let hosts = vec!["imgur.com".to_string()];
+        assert!(host_matches("https://imgur.com/a/xyz", &hosts));
+        assert!(host_matches("https://i.imgur.com/xyz.png", &hosts));
+        assert!(!host_matches("https://notimgur.com/xyz", &hosts));
+        assert!(!host_matches("not a url", &hosts));
+    }
+
+    #[test]
+    fn generic_tool_from_config_template() {
+        let config = parse_config(
+            r#"
+            [tools.podcast]
+            command = "podcast-fetch"
+            args = ["--out", "{output_dir}", "{url}"]
+            hosts = ["podcasts.example.com"]
+            "#,
+        )
+        .expect("Config should parse");
+        let tool_config = config.tools["podcast"].clone();
+        assert_eq!(vec![0], tool_config.success_codes);
+        let downloader = GenericCommand::new(
+            "podcast".to_string(),
+            PathBuf::from("podcast-fetch"),
+            tool_config,
+        );
+        let entry = parse_line("https://podcasts.example.com/1", 1).expect("Line should parse");
+        let command = downloader.build_command(&entry, Path::new("out"));
+        let args: Vec<String> = command
+            .get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect();
+        let out = std::path::absolute("out").expect("Path should resolve");
+        assert_eq!(
+            vec![
+                "--out",
+                out.to_str().unwrap(),
+                "https://podcasts.example.com/1"
+            ],
+            args
+        );
+    }
+
+    #[test]
+    fn generic_tool_writes_into_the_output_dir() {
+        let config = parse_config(
+            r#"
+            [tools.touch]
+            command = "sh"
+            args = ["-c", "touch {output_dir}/{name}.txt"]
+            "#,
+        )
+        .expect("Config should parse");
+        let downloader = GenericCommand::new(
+            "touch".to_string(),
+            PathBuf::from("sh"),
+            config.tools["touch"].clone(),
+        );
+        let output_dir = Path::new("generic-output-test/20240101");
+        fs::create_dir_all(output_dir).expect("Could not create the output dir");
+        let entry = parse_line("https://example.com/1 | name=clip", 1).expect("Line should parse");
+        let status = downloader
+            .build_command(&entry, output_dir)
+            .status()
+            .expect("Could not run sh");
+        let landed = output_dir.join("clip.txt").exists();
+        let nested = output_dir.join("generic-output-test").exists();
+        fs::remove_dir_all("generic-output-test").expect("Could not remove the output dir");
+        assert!(status.success());
+        assert!(landed, "The file should be in the output dir");
+        assert!(
+            !nested,
+            "The output dir should not be repeated inside itself"
+        );
+    }
+
+    #[test]
+    fn config_rejects_unknown_keys() {
+        assert!(parse_config("[tools.x]\ncommand = \"x\"\nunknown = 1\n").is_err());
+        assert!(parse_config("").is_ok());
+    }
+
+    #[test]
+    fn default_profile_is_the_built_in_flags() {
+        let config = parse_config("").expect("Config should parse");
+        let args = config
+            .profile_args(DEFAULT_PROFILE)
+            .expect("Default profile should always be there");
+        assert_eq!(YT_DLP_DEFAULT_ARGS.to_vec(), args);
+        assert_eq!(None, config.profile_args("audio-only"));
+    }
+
+    #[test]
+    fn profile_from_config() {
+        let config = parse_config(
+            r#"
+            [profiles.audio-only]
+            args = ["--extract-audio", "--audio-format", "mp3"]
+            "#,
+        )
+        .expect("Config should parse");
+        assert_eq!(
+            Some(vec![
+                "--extract-audio".to_string(),
+                "--audio-format".to_string(),
+                "mp3".to_string()
+            ]),
+            config.profile_args("audio-only")
+        );
+        assert_eq!(
+            vec![DEFAULT_PROFILE.to_string(), "audio-only".to_string()],
+            config.profile_names()
+        );
+    }
+
+    #[test]
+    fn config_layers_merge_key_by_key() {
+        let system = ConfigLayer::parse(
+            "/etc/yt-parallel/config.toml",
+            r#"
+            move-target = "/mnt/nas/youtube/"
+            jobs = 2
+            [profiles.audio-only]
+            args = ["--extract-audio"]
+            [log]
+            level = "debug"
+            "#,
+        )
+        .expect("System config should parse");
+        let user = ConfigLayer::parse(
+            "user.toml",
+            r#"
+            jobs = 6
+            [profiles.archive]
+            args = ["--embed-metadata"]
+            [log]
+            file = "yt.log"
+            "#,
+        )
+        .expect("User config should parse");
+        let mut commandline = ConfigLayer::new("commandline");
+        commandline.set("log.level", "trace");
+        let config = merge_layers(&[system, user, commandline]).expect("Layers should merge");
+        assert_eq!(Some("/mnt/nas/youtube/".to_string()), config.move_target);
+        assert_eq!(6, config.jobs);
+        assert_eq!("yt-dlp", config.download_tool);
+        assert_eq!(
+            vec![DEFAULT_PROFILE, "archive", "audio-only"],
+            config.profile_names()
+        );
+        assert_eq!(Some("yt.log".to_string()), config.log.file);
+        assert_eq!(Some("trace".to_string()), config.log.level);
+        assert_eq!(
+            "/etc/yt-parallel/config.toml",
+            config.origins["move-target"]
+        );
+        assert_eq!("user.toml", config.origins["jobs"]);
+        assert_eq!("commandline", config.origins["log.level"]);
+        assert_eq!("default", config.origins["download-tool"]);
+    }
+
+    #[test]
+    fn config_from_environment_variables() {
+        let vars = [
+            ("YT_PARALLEL_JOBS", "3"),
+            ("YT_PARALLEL_LOG_ROTATE", "10MiB"),
+            ("YT_PARALLEL_MOVE_TARGET", "/output/"),
+            ("YT_PARALLEL_GALLERY_DL_HOSTS", r#"["imgur.com"]"#),
+            ("YT_PARALLEL_TIMEOUT", "30"),
+            ("HOME", "/root"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        let layers = env_layers(vars, &["YT_PARALLEL_TIMEOUT".to_string()])
+            .expect("Variables should be valid");
+        let config = merge_layers(&layers).expect("Layers should merge");
+        assert_eq!(3, config.jobs);
+        assert_eq!(Some("10MiB".to_string()), config.log.rotate);
+        assert_eq!(Some("/output/".to_string()), config.move_target);
+        assert_eq!(vec!["imgur.com".to_string()], config.gallery_dl.hosts);
+        assert_eq!("environment YT_PARALLEL_JOBS", config.origins["jobs"]);
+        assert_eq!(4, layers.len());
+        for bad in [("YT_PARALLEL_JOBS", "many"), ("YT_PARALLEL_NOT_A_KEY", "1")] {
+            let vars = [(bad.0.to_string(), bad.1.to_string())];
+            assert!(
+                env_layers(vars, &[]).is_err(),
+                "{} should be rejected",
+                bad.0
+            );
+        }
+    }
+
+    #[test]
+    fn every_option_has_an_environment_variable() {
+        for arg in Args::command().get_arguments() {
+            let id = arg.get_id().as_str();
+            if id == "help" || id == "version" {
+                continue;
+            }
+            let env = arg.get_env().map(|e| e.to_string_lossy().into_owned());
+            assert_eq!(
+                Some(format!("YT_PARALLEL_{}", id.to_uppercase())),
+                env,
+                "--{id} should have an environment variable"
+            );
+        }
+    }
+
+    #[test]
+    fn plain_url_line() {
+        let entry = parse_line("https://youtu.be/abc", 3).expect("Line should parse");
+        assert_eq!(
+            VideoEntry {
+                url: "https://youtu.be/abc".to_string(),
+                line_number: 3,
+                ..Default::default()
+            },
+            entry
+        );
+        assert_eq!(PathBuf::from("20240101"), entry.output_dir("20240101"));
+    }
+
+    #[test]
+    fn line_with_options() {
+        let entry = parse_line(
+            "https://youtu.be/abc | profile=audio-only | subdir=podcasts | name=%(title)s.%(ext)s",
+            1,
+        )
+        .expect("Line should parse");
+        assert_eq!("https://youtu.be/abc", entry.url);
+        assert_eq!(Some("audio-only".to_string()), entry.profile);
+        assert_eq!(Some("%(title)s.%(ext)s".to_string()), entry.name);
+        assert_eq!(
+            Path::new("20240101").join("podcasts"),
+            entry.output_dir("20240101")
+        );
+    }
+
+    #[test]
+    fn line_with_bad_options() {
+        assert!(parse_line("https://youtu.be/abc | colour=blue", 1).is_err());
+        assert!(parse_line("https://youtu.be/abc | profile", 1).is_err());
+        assert!(parse_line("https://youtu.be/abc | profile=", 1).is_err());
+        assert!(parse_line("https://youtu.be/abc | subdir=../escape", 1).is_err());
+        assert!(parse_line("https://youtu.be/abc | subdir=/absolute", 1).is_err());
+        assert!(parse_line("https://youtu.be/abc | name=../../x.%(ext)s", 1).is_err());
+        assert!(parse_line("https://youtu.be/abc | name=/tmp/%(title)s.%(ext)s", 1).is_err());
+        assert!(
+            parse_line(
+                "https://youtu.be/abc | name=%(uploader)s/%(title)s.%(ext)s",
+                1
+            )
+            .is_ok()
+        );
+        assert!(
+            parse_line(
+                "https://youtu.be/abc | name=%(upload_date>%Y/%m)s/x.%(ext)s",
+                1
+            )
+            .is_ok()
+        );
+        assert!(parse_line(" | profile=audio-only", 1).is_err());
+    }
+
+    #[test]
+    fn yt_dlp_command_uses_entry_profile_and_name() {
+        let mut profiles = BTreeMap::new();
+        profiles.insert(DEFAULT_PROFILE.to_string(), vec!["--default".to_string()]);
+        profiles.insert("audio-only".to_string(), vec!["-x".to_string()]);
+        let downloader = YtDlp::new(
+            PathBuf::from("yt-dlp"),
+            profiles,
+            DEFAULT_PROFILE.to_string(),
+        );
+        let args_for = |line: &str| -> Vec<String> {
+            let entry = parse_line(line, 1).expect("Line should parse");
+            downloader
+                .build_command(&entry, Path::new("out"))
+                .get_args()
+                .map(|a| a.to_string_lossy().to_string())
+                .collect()
+        };
+        assert_eq!(
+            vec!["--default", "--newline", "https://a.b/c"],
+            args_for("https://a.b/c")
+        );
+        assert_eq!(
+            vec![
+                "-x",
+                "--newline",
+                "--output",
+                "%(title)s.%(ext)s",
+                "https://a.b/c"
+            ],
+            args_for("https://a.b/c | profile=audio-only | name=%(title)s.%(ext)s")
+        );
+    }
+
+    #[test]
+    fn video_list_skips_comments_and_blank_lines() {
+        let list = "# my list\n\
+            \n   \n\
+            https://youtu.be/abc   # inline comment\n\
+            https://example.com/page#part\n\
+            \tnotes about stuff\n\
+            ftp://example.com/file\n";
+        let (entries, rejected) = read_video_list(list.as_bytes());
+        let urls: Vec<&str> = entries.iter().map(|e| e.url.as_str()).collect();
+        assert_eq!(
+            vec!["https://youtu.be/abc", "https://example.com/page#part"],
+            urls
+        );
+        assert_eq!(4, entries[0].line_number);
+        let rejected_lines: Vec<usize> = rejected.iter().map(|r| r.line_number).collect();
+        assert_eq!(vec![6, 7], rejected_lines);
+    }
+
+    #[test]
+    fn url_shorthands() {
+        assert_eq!(
+            Ok("https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string()),
+            normalize_url("dQw4w9WgXcQ")
+        );
+        assert_eq!(
+            Ok("https://youtu.be/dQw4w9WgXcQ".to_string()),
+            normalize_url("youtu.be/dQw4w9WgXcQ")
+        );
+        assert!(normalize_url("stray").is_err());
+        assert!(normalize_url("mailto:someone@example.com").is_err());
+    }
+
+    #[test]
+    fn youtube_url_variants_are_canonical() {
+        let canonical = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+        for variant in [
+            "https://youtu.be/dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ?si=abcdef",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=30",
+            "https://youtube.com/watch?v=dQw4w9WgXcQ&list=PL123&index=2",
+            "https://m.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+        ] {
+            assert_eq!(canonical, canonical_url(variant), "{variant}");
+        }
+        assert_eq!(
+            "https://www.youtube.com/playlist?list=PL123",
+            canonical_url("https://youtube.com/playlist?list=PL123&utm_source=share")
+        );
+        assert_eq!(
+            "https://example.com/video?id=1",
+            canonical_url("https://example.com/video?id=1&utm_campaign=x&fbclid=y")
+        );
+    }
+
+    #[test]
+    fn duplicates_are_merged_into_the_first() {
+        let (entries, _) = read_video_list(
+            "https://youtu.be/dQw4w9WgXcQ\n\
+             https://example.com/other\n\
+             https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=30\n"
+                .as_bytes(),
+        );
+        let (kept, merged) = dedupe_entries(entries);
+        assert_eq!(2, kept.len());
+        assert_eq!(1, merged.len());
+        assert_eq!(3, merged[0].0.line_number);
+        assert_eq!(1, merged[0].1);
+    }
+
+    #[test]
+    fn duplicates_with_other_options_are_kept() {
+        let (entries, _) = read_video_list(
+            "https://youtu.be/dQw4w9WgXcQ | subdir=music\n\
+             https://www.youtube.com/watch?v=dQw4w9WgXcQ | profile=audio-only\n\
+             https://youtu.be/dQw4w9WgXcQ | profile=audio-only\n"
+                .as_bytes(),
+        );
+        let (kept, merged) = dedupe_entries(entries);
+        assert_eq!(
+            vec![1, 2],
+            kept.iter().map(|e| e.line_number).collect::<Vec<_>>()
+        );
+        assert_eq!(1, merged.len());
+        assert_eq!(3, merged[0].0.line_number);
+        assert_eq!(2, merged[0].1);
+    }
+
+    #[test]
+    fn archive_keys_from_yt_dlp_output() {
+        let stdout = "[youtube] Extracting URL: https://youtu.be/dQw4w9WgXcQ\n\
+            [youtube] dQw4w9WgXcQ: Downloading webpage\n\
+            [youtube] dQw4w9WgXcQ: Downloading android player API JSON\n\
+            [info] dQw4w9WgXcQ: Downloading 1 format(s): 22\n\
+            [youtube:tab] PL123: Downloading webpage\n";
+        let downloader = YtDlp::new(
+            PathBuf::from("yt-dlp"),
+            BTreeMap::new(),
+            DEFAULT_PROFILE.to_string(),
+        );
+        assert_eq!(
+            vec!["youtube dQw4w9WgXcQ".to_string()],
+            downloader.archive_keys(stdout)
+        );
+        assert_eq!(
+            Some("youtube dQw4w9WgXcQ".to_string()),
+            archive_key_for_url("https://youtu.be/dQw4w9WgXcQ")
+        );
+        assert_eq!(None, archive_key_for_url("https://example.com/video"));
+    }
+
+    #[test]
+    fn archive_is_kept_across_loads() {
+        let archive_file = "test_archive.txt";
+        let _ = fs::remove_file(archive_file);
+        let archive = Archive::load(Path::new(archive_file)).expect("Empty archive should load");
+        assert!(!archive.contains("youtube dQw4w9WgXcQ"));
+        archive
+            .record("youtube dQw4w9WgXcQ")
+            .expect("Could not write the archive");
+        archive
+            .record("youtube dQw4w9WgXcQ")
+            .expect("Could not write the archive");
+        let reloaded = Archive::load(Path::new(archive_file)).expect("Archive should load");
+        let content = fs::read_to_string(archive_file).expect("Archive file should be there");
+        fs::remove_file(archive_file).expect("Could not remove the archive file");
+        assert!(reloaded.contains("youtube dQw4w9WgXcQ"));
+        assert_eq!("youtube dQw4w9WgXcQ\n", content);
+    }
+
+    #[test]
+    fn stderr_tail_keeps_last_lines() {
+        let stderr = (1..=8)
+            .map(|n| format!("line {n}"))
+            .collect::<Vec<String>>()
+            .join("\n\n");
+        let tail = stderr_tail(&stderr);
+        assert_eq!(STDERR_TAIL_LINES, tail.len());
+        assert_eq!("line 8", tail.last().unwrap());
+        assert!(stderr_tail("").is_empty());
+    }
+
+    #[test]
+    fn summary_lists_failures_after_successes() {
+        let failed = JobResult::failed(
+            "https://a.b/fail",
+            1,
+            "yt-dlp",
+            "ERROR: Video unavailable".to_string(),
+        );
+        let succeeded = JobResult {
+            url: "https://a.b/ok".to_string(),
+            line_number: 2,
+            tool: "yt-dlp".to_string(),
+            success: true,
+            exit_code: Some(0),
+            timed_out: false,
+            interrupted: false,
+            duration: std::time::Duration::from_secs(61),
+            attempts: 2,
+            output_files: vec!["ok.mp4".to_string()],
+            bytes: 1024,
+            log_file: None,
+            stderr_tail: Vec::new(),
+        };
+        let lines = render_summary(&[failed, succeeded]);
+        assert_eq!(4, lines.len());
+        assert!(lines[1].starts_with("OK"));
+        assert!(lines[1].contains("00:01:01"));
+        assert!(lines[1].ends_with("ok.mp4"));
+        assert!(lines[2].starts_with("FAILED"));
+        assert!(lines[2].ends_with("ERROR: Video unavailable"));
+        assert_eq!("1 downloaded, 1 failed, 2 in total", lines[3]);
+    }
+
+    #[test]
+    fn failed_list_reads_back_as_video_list() {
+        let list_file = "test_failed_list.txt";
+        let entry = parse_line("https://a.b/c | profile=audio-only | subdir=pods", 4)
+            .expect("Line should parse");
+        assert_eq!(
+            "https://a.b/c | profile=audio-only | subdir=pods",
+            entry.to_line()
+        );
+        write_video_list(
+            Path::new(list_file),
+            &[(
+                entry.clone(),
+                "ERROR: HTTP Error 429:\nToo Many Requests".to_string(),
+            )],
+        )
+        .expect("Could not write the list");
+        let content = fs::read_to_string(list_file).expect("List should be there");
+        let (entries, rejected) = read_video_list(content.as_bytes());
+        fs::remove_file(list_file).expect("Could not remove the list");
+        assert_eq!(
+            "https://a.b/c | profile=audio-only | subdir=pods   # ERROR: HTTP Error 429: Too Many Requests\n",
+            content
+        );
+        assert!(rejected.is_empty());
+        assert_eq!(entry.to_line(), entries[0].to_line());
+    }
+
+    #[test]
+    fn date_patterns_are_filled_in() {
+        let datetime = chrono::Local.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        assert_eq!(
+            Ok("failed-20240102.txt".to_string()),
+            format_date_pattern("failed-%Y%m%d.txt", &datetime)
+        );
+        assert!(format_date_pattern("failed-%Q.txt", &datetime).is_err());
+    }
+
+    #[test]
+    fn folder_name_from_template() {
+        let datetime = chrono::Local.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        let tokens = FolderTokens {
+            list_name: "talks/50%".to_string(),
+            run_id: "1234".to_string(),
+            hostname: "nas".to_string(),
+        };
+        assert_eq!(
+            Ok("20240102-talks_50%-nas-1234".to_string()),
+            render_folder_name("%Y%m%d-{list_name}-{hostname}-{run_id}", &datetime, &tokens)
+        );
+        assert!(render_folder_name("%Y-{list}", &datetime, &tokens).is_err());
+        assert!(render_folder_name("batches/%Y", &datetime, &tokens).is_err());
+    }
+
+    #[test]
+    fn failures_are_classified() {
+        assert_eq!(
+            FailureKind::Retryable,
+            classify_failure(
+                "ERROR: unable to download video data: HTTP Error 429: Too Many Requests"
+            )
+        );
+        assert_eq!(
+            FailureKind::Retryable,
+            classify_failure(
+                "ERROR: [youtube] abc: Video unavailable. This content isn't available, try again later."
+            )
+        );
+        assert_eq!(
+            FailureKind::Permanent,
+            classify_failure(
+                "ERROR: [youtube] abc: Private video. Sign in if you've been granted access"
+            )
+        );
+        assert_eq!(
+            FailureKind::Permanent,
+            classify_failure(
+                "ERROR: [youtube] abc: The uploader has not made this video available in your country"
+            )
+        );
+        assert_eq!(
+            FailureKind::Permanent,
+            classify_failure("ERROR: Unable to download webpage: HTTP Error 404: Not Found")
+        );
+        assert_eq!(
+            FailureKind::Retryable,
+            classify_failure(
+                "ERROR: Unable to download webpage: HTTP Error 503: Service Unavailable"
+            )
+        );
+        assert_eq!(
+            FailureKind::Unknown,
+            classify_failure("ERROR: something odd")
+        );
+        assert!(FailureKind::Unknown.is_retryable());
+        assert!(!FailureKind::Permanent.is_retryable());
+    }
+
+    #[test]
+    fn retry_delay_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_delay: std::time::Duration::from_secs(10),
+            max_delay: std::time::Duration::from_secs(60),
+        };
+        for _ in 0..20 {
+            let first = policy.delay(1);
+            assert!(first >= std::time::Duration::from_secs(5));
+            assert!(first <= std::time::Duration::from_secs(10));
+            let second = policy.delay(2);
+            assert!(second >= std::time::Duration::from_secs(10));
+            assert!(second <= std::time::Duration::from_secs(20));
+            assert!(policy.delay(9) <= std::time::Duration::from_secs(60));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tool_output_is_captured() {
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg("echo out; echo err >&2; exit 3");
+        let output = run_tool(
+            &mut command,
+            &TimeLimits::default(),
+            &Shutdown::default(),
+            &mut |_| {},
+        )
+        .expect("sh should run");
+        assert_eq!(Some(3), output.status.and_then(|s| s.code()));
+        assert_eq!(b"out\n".to_vec(), output.stdout);
+        assert_eq!(b"err\n".to_vec(), output.stderr);
+        assert_eq!(None, output.killed);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn silent_tool_is_killed_as_stalled() {
+        let limit = std::time::Duration::from_millis(300);
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg("echo started; sleep 10");
+        let started = std::time::Instant::now();
+        let output = run_tool(
+            &mut command,
+            &TimeLimits {
+                timeout: None,
+                stall_timeout: Some(limit),
+            },
+            &Shutdown::default(),
+            &mut |_| {},
+        )
+        .expect("sh should run");
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(None, output.status);
+        assert_eq!(Some(KillReason::Stalled(limit)), output.killed);
+        assert_eq!(
+            FailureKind::Retryable,
+            classify_failure(&output.killed.unwrap().message())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn busy_tool_is_killed_at_the_timeout() {
+        let limit = std::time::Duration::from_millis(300);
+        let mut command = std::process::Command::new("sh");
+        command
+            .arg("-c")
+            .arg("while true; do echo busy; sleep 0.05; done");
+        let output = run_tool(
+            &mut command,
+            &TimeLimits {
+                timeout: Some(limit),
+                stall_timeout: Some(std::time::Duration::from_secs(60)),
+            },
+            &Shutdown::default(),
+            &mut |_| {},
+        )
+        .expect("sh should run");
+        assert_eq!(Some(KillReason::TimedOut(limit)), output.killed);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn second_stop_request_kills_the_tool() {
+        let shutdown = Shutdown::default();
+        assert!(!shutdown.stop_requested());
+        assert_eq!(1, shutdown.request());
+        assert!(shutdown.stop_requested());
+        assert!(!shutdown.kill_requested());
+        assert!(shutdown.sleep(std::time::Duration::from_secs(10)));
+        let killer = shutdown.clone();
+        let signaller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            killer.request();
+        });
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg("echo started; sleep 10");
+        let started = std::time::Instant::now();
+        let output = run_tool(&mut command, &TimeLimits::default(), &shutdown, &mut |_| {})
+            .expect("sh should run");
+        signaller.join().expect("Signaller should finish");
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(Some(KillReason::Interrupted), output.killed);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn leaving_right_away_kills_the_running_tools() {
+        use std::os::unix::process::CommandExt;
+        let shutdown = Shutdown::default();
+        let mut child = std::process::Command::new("sh")
+            .arg("-c")
+            .arg("sleep 30 & wait")
+            .process_group(0)
+            .spawn()
+            .expect("sh should run");
+        let tracked = shutdown.track(child.id());
+        let started = std::time::Instant::now();
+        shutdown.kill_children();
+        let status = child.wait().expect("sh should be reaped");
+        drop(tracked);
+        assert!(!status.success());
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn batch_state_needs_a_state_file_to_resume() {
+        let folder = "test_batch_state_missing";
+        fs::create_dir_all(folder).expect("Could not create the folder");
+        let loaded = BatchState::load(folder);
+        fs::remove_dir_all(folder).expect("Could not remove the folder");
+        assert!(loaded.is_err());
+        assert!(BatchState::load("test_batch_state_not_there").is_err());
+    }
+
+    #[test]
+    fn batch_state_survives_a_restart() {
+        let folder = "test_batch_state";
+        fs::create_dir_all(folder).expect("Could not create the folder");
+        let done = parse_line("https://a.b/done | subdir=x", 1).expect("Line should parse");
+        let cut_off = parse_line("https://a.b/cut-off", 2).expect("Line should parse");
+        let state = BatchState::new(folder);
+        state
+            .start(&[done.clone(), cut_off.clone()])
+            .expect("Could not write the state");
+        state.running(&done).expect("Could not write the state");
+        let mut result = JobResult::failed(&done.url, 1, "yt-dlp", String::new());
+        result.success = true;
+        result.attempts = 2;
+        state
+            .finished(&done, &result)
+            .expect("Could not write the state");
+        state.running(&cut_off).expect("Could not write the state");
+
+        // The batch is cut off here, a new run loads what was written.
+        let resumed = BatchState::load(folder).expect("State should load");
+        let written = fs::read_to_string(Path::new(folder).join(STATE_FILE_NAME))
+            .expect("State file should be there");
+        resumed.remove().expect("Could not remove the state file");
+        let state_left = Path::new(folder).join(STATE_FILE_NAME).exists();
+        fs::remove_dir_all(folder).expect("Could not remove the folder");
+        assert!(resumed.is_done(&done));
+        assert!(!resumed.is_done(&cut_off));
+        assert!(written.contains("state = \"running\""));
+        assert!(written.contains("attempts = 2"));
+        assert!(!state_left);
+    }
+
+    #[test]
+    fn yt_dlp_progress_is_parsed() {
+        let progress = parse_yt_dlp_progress(
+            "[download]  42.3% of ~  1.20GiB at    3.10MiB/s ETA 00:40 (frag 3/10)",
+        )
+        .expect("Line should give progress");
+        assert_eq!(Phase::Downloading, progress.phase);
+        assert_eq!(Some(42.3), progress.percent);
+        assert_eq!(parse_size("1.20GiB"), progress.total_bytes);
+        assert_eq!(Some(545_031_349), progress.downloaded_bytes);
+        assert_eq!(Some(3_250_585), progress.speed);
+        assert_eq!(Some(std::time::Duration::from_secs(40)), progress.eta);
+
+        let unknown =
+            parse_yt_dlp_progress("[download]   0.0% of 10.00MiB at  Unknown B/s ETA Unknown")
+                .expect("Line should give progress");
+        assert_eq!(None, unknown.speed);
+        assert_eq!(None, unknown.eta);
+        assert_eq!(
+            Phase::Merging,
+            parse_yt_dlp_progress("[Merger] Merging formats into \"a.mkv\"")
+                .unwrap()
+                .phase
+        );
+        assert_eq!(
+            Phase::SponsorBlock,
+            parse_yt_dlp_progress("[SponsorBlock] Found 2 segments in the SponsorBlock database")
+                .unwrap()
+                .phase
+        );
+        assert_eq!(
+            Phase::Extracting,
+            parse_yt_dlp_progress("[youtube] abc: Downloading webpage")
+                .unwrap()
+                .phase
+        );
+        assert_eq!(
+            None,
+            parse_yt_dlp_progress("Deleting original file a.f137.mp4")
+        );
+    }
+
+    #[test]
+    fn progress_is_rendered_for_the_log() {
+        let progress = parse_yt_dlp_progress(
+            "[download]  42.3% of ~  1.20GiB at    3.10MiB/s ETA 00:40 (frag 3/10)",
+        )
+        .expect("Line should give progress");
+        assert_eq!(
+            "Downloading 42.3% of 1.20GiB at 3.10MiB/s ETA 00:00:40",
+            render_progress(&progress)
+        );
+        assert_eq!("Merging", render_progress(&Progress::phase(Phase::Merging)));
+        assert_eq!("512.00B", render_size(512));
+        assert_eq!("1.50KiB", render_size(1536));
+        assert_eq!("2.00TiB", render_size(2 * 1024 * 1024 * 1024 * 1024));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tool_lines_are_streamed_while_it_runs() {
+        let mut command = std::process::Command::new("sh");
+        command
+            .arg("-c")
+            .arg("printf 'one\\rtwo\\n'; sleep 0.3; printf 'three'");
+        let mut lines: Vec<(String, std::time::Instant)> = Vec::new();
+        let output = run_tool(
+            &mut command,
+            &TimeLimits::default(),
+            &Shutdown::default(),
+            &mut |line| lines.push((line.to_string(), std::time::Instant::now())),
+        )
+        .expect("sh should run");
+        let texts: Vec<&str> = lines.iter().map(|(line, _)| line.as_str()).collect();
+        assert_eq!(vec!["one", "two", "three"], texts);
+        // The first lines come in before the tool is done
+        assert!(lines[2].1.duration_since(lines[1].1) > std::time::Duration::from_millis(200));
+        assert_eq!(b"one\rtwo\nthree".to_vec(), output.stdout);
+    }
+
+    #[test]
+    fn tool_version_is_the_first_line() {
+        let cargo = PathBuf::from(std::env::var("CARGO").expect("Tests run under cargo"));
+        let version =
+            tool_version(&cargo, &Shutdown::default()).expect("Cargo should tell its version");
+        assert!(version.starts_with("cargo "), "{version}");
+        assert_eq!(
+            None,
+            tool_version(Path::new("IAmNotThere"), &Shutdown::default())
+        );
+    }
+
+    #[test]
+    fn report_is_written_as_json() {
+        let report_file = "test_report.json";
+        let started = chrono::Local.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        let finished = started + Duration::seconds(90);
+        let mut timed_out = JobResult::failed("https://a.b/slow", 2, "yt-dlp", String::new());
+        timed_out.timed_out = true;
+        timed_out.exit_code = None;
+        timed_out.stderr_tail = vec!["Killed, timed out after 60 seconds".to_string()];
+        let mut downloaded = JobResult::failed("https://a.b/ok", 1, "yt-dlp", String::new());
+        downloaded.success = true;
+        downloaded.output_files = vec!["pods/ok.mp4".to_string()];
+        downloaded.bytes = 2048;
+        let report = RunReport::new(
+            started,
+            finished,
+            "20240102",
+            &[timed_out, downloaded],
+            Some(MoveOutcome {
+                target: "/nas".to_string(),
+                success: true,
+                duration: std::time::Duration::from_millis(1500),
+                decisions: Vec::new(),
+            }),
+            Some("2024.12.23".to_string()),
+            5,
+        );
+        report
+            .write(Path::new(report_file))
+            .expect("Could not write the report");
+        let content = fs::read_to_string(report_file).expect("Report should be there");
+        fs::remove_file(report_file).expect("Could not remove the report");
+        let json: serde_json::Value =
+            serde_json::from_str(&content).expect("Report should be JSON");
+        assert_eq!(90.0, json["duration_seconds"]);
+        assert_eq!(5, json["exit_code"]);
+        assert_eq!(env!("CARGO_PKG_VERSION"), json["version"]);
+        assert_eq!("2024.12.23", json["tool_version"]);
+        assert_eq!(1, json["failed"]);
+        assert_eq!(true, json["move"]["success"]);
+        assert_eq!(1.5, json["move"]["duration_seconds"]);
+        let downloads = json["downloads"]
+            .as_array()
+            .expect("Downloads should be a list");
+        assert_eq!("ok", downloads[0]["status"]);
+        assert_eq!(2048, downloads[0]["bytes"]);
+        assert_eq!("pods/ok.mp4", downloads[0]["output_files"][0]);
+        assert!(downloads[0]["error"].is_null());
+        assert_eq!("timeout", downloads[1]["status"]);
+        assert_eq!("Killed, timed out after 60 seconds", downloads[1]["error"]);
+    }
+
+    #[test]
+    fn json_log_lines_carry_the_job() {
+        let run_id = uuid::Uuid::new_v4();
+        let record_line = || {
+            json_log_line(
+                &log::Record::builder()
+                    .args(format_args!("Downloading"))
+                    .level(log::Level::Warn)
+                    .module_path(Some("yt_parallel"))
+                    .build(),
+                &run_id,
+            )
+        };
+        let line: serde_json::Value =
+            serde_json::from_str(&record_line()).expect("Line should be JSON");
+        assert_eq!("WARN", line["level"]);
+        assert_eq!(run_id.to_string(), line["run_id"]);
+        assert_eq!("yt_parallel", line["module"]);
+        assert_eq!("Downloading", line["message"]);
+        assert!(line.get("url").is_none());
+
+        set_job_context(Some(JobContext {
+            url: "https://a.b/c".to_string(),
+            job_id: "line-3".to_string(),
+            attempt: 1,
+        }));
+        set_job_attempt(2);
+        let line: serde_json::Value =
+            serde_json::from_str(&record_line()).expect("Line should be JSON");
+        set_job_context(None);
+        assert_eq!("https://a.b/c", line["url"]);
+        assert_eq!("line-3", line["job_id"]);
+        assert_eq!(2, line["attempt"]);
+        assert!(!record_line().contains('\n'));
+    }
+
+    #[test]
+    fn log_file_rotates_by_size_and_keeps_the_newest() {
+        let folder = "test_log_rotation";
+        let log_path = Path::new(folder).join("run.log");
+        let mut log_file =
+            RotatingFile::open(&log_path, Rotation::Size(10), 2).expect("Log file should open");
+        for line in ["first 1\n", "second\n", "third 3\n", "fourth\n"] {
+            std::io::Write::write_all(&mut log_file, line.as_bytes())
+                .expect("Line should be written");
+        }
+        let read = |name: &str| fs::read_to_string(Path::new(folder).join(name)).ok();
+        let (current, newest, oldest, dropped) = (
+            read("run.log"),
+            read("run.log.1"),
+            read("run.log.2"),
+            read("run.log.3"),
+        );
+        fs::remove_dir_all(folder).expect("Could not remove the folder");
+        assert_eq!(Some("fourth\n".to_string()), current);
+        assert_eq!(Some("third 3\n".to_string()), newest);
+        assert_eq!(Some("second\n".to_string()), oldest);
+        assert_eq!(None, dropped);
+    }
+
+    #[test]
+    fn log_rotation_is_parsed() {
+        assert_eq!(Ok(Rotation::Daily), parse_rotation("daily"));
+        assert_eq!(Ok(Rotation::Never), parse_rotation("never"));
+        assert_eq!(Ok(Rotation::Size(10 << 20)), parse_rotation("10MiB"));
+        assert!(parse_rotation("weekly").is_err());
+        assert!(parse_rotation("0B").is_err());
+        let config = parse_config("[log]\nfile = \"yt.log\"\nlevel = \"debug\"\nkeep = 3\n")
+            .expect("Config should parse");
+        assert_eq!(Some("yt.log".to_string()), config.log.file);
+        assert_eq!(Some(3), config.log.keep);
+    }
+
+    #[test]
+    fn job_log_keeps_every_attempt_and_survives_bad_utf8() {
+        let folder = "test_job_log";
+        let entry = parse_line("https://a.b/c", 7).expect("Line should parse");
+        let log_path = job_log_path(&format!("{folder}/20240101"), "run-1", &entry);
+        let command = std::process::Command::new("yt-dlp");
+        append_job_log(
+            &log_path,
+            1,
+            &command,
+            "exit status: 1",
+            b"partial",
+            b"bad \xff byte\n",
+        )
+        .expect("Log should be written");
+        append_job_log(&log_path, 2, &command, "exit status: 0", b"done\n", b"")
+            .expect("Log should be written");
+        let content = fs::read_to_string(&log_path).expect("Log should be readable as text");
+        fs::remove_dir_all(folder).expect("Could not remove the folder");
+        assert_eq!(
+            Path::new(folder).join("logs/20240101-run-1/line-7.log"),
+            log_path
+        );
+        // A later batch with the same folder name, once this one has been moved, gets files of its own
+        assert_eq!(
+            Path::new("logs/20240101-run-2/line-7.log"),
+            job_log_path("20240101", "run-2", &entry)
+        );
+        assert!(content.contains("=== Attempt 1 at "));
+        assert!(content.contains("--- stdout ---\npartial\n--- stderr ---\nbad \u{fffd} byte\n"));
+        assert!(content.contains("--- exit status: 1 ---\n=== Attempt 2 at "));
+        assert!(
+            content.ends_with("--- stdout ---\ndone\n--- stderr ---\n--- exit status: 0 ---\n")
+        );
+
+        let mut failed = JobResult::failed("https://a.b/c", 7, "yt-dlp", "ERROR: gone".to_string());
+        failed.log_file = Some(log_path.display().to_string());
+        let lines = render_summary(&[failed]);
+        assert!(lines[1].ends_with(&format!("ERROR: gone (see {})", log_path.display())));
+    }
 }
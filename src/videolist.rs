@@ -0,0 +1,351 @@
+use log::warn;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::path::{Component, Path};
+use url::Url;
+
+/// One line of the video list, the url with the options that were given for it.
+/// A line is either a bare url or a url followed by `|` separated options:
+/// `https://youtu.be/abc | profile=audio-only | subdir=podcasts | name=%(title)s.%(ext)s`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VideoEntry {
+    pub url: String,
+    /// Line number in the list, starting at 1, used in the logging
+    pub line_number: usize,
+    /// The yt-dlp profile to use instead of the one passed with `--profile`
+    pub profile: Option<String>,
+    /// Folder inside the download folder to place the download in
+    pub subdir: Option<String>,
+    /// Output name template handed to the tool
+    pub name: Option<String>,
+}
+
+impl VideoEntry {
+    /// The entry as a line of the video list, with the options it was given
+    pub fn to_line(&self) -> String {
+        let mut line = self.url.clone();
+        for (key, value) in [
+            ("profile", &self.profile),
+            ("subdir", &self.subdir),
+            ("name", &self.name),
+        ] {
+            if let Some(value) = value {
+                line.push_str(&format!(" | {key}={value}"));
+            }
+        }
+        line
+    }
+
+    /// The id of the download of this entry in the logging, unique within a video list
+    pub fn job_id(&self) -> String {
+        format!("line-{}", self.line_number)
+    }
+
+    /// The folder this entry downloads into
+    /// # Parameters
+    /// folder_name - The download folder of the batch
+    /// # Returns
+    /// The download folder, or the subdir in it when one is set.
+    pub fn output_dir(&self, folder_name: &str) -> std::path::PathBuf {
+        match &self.subdir {
+            Some(subdir) => Path::new(folder_name).join(subdir),
+            None => Path::new(folder_name).to_path_buf(),
+        }
+    }
+}
+
+/// A line of the video list that will not be downloaded, kept to report it to the user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedLine {
+    pub line_number: usize,
+    pub line: String,
+    pub reason: String,
+}
+
+/// Read the video list, comments and blank lines are skipped, every other line must be a valid entry.
+/// A comment is a line starting with `#`, or the rest of a line from a `#` that follows a space or tab.
+/// # Parameters
+/// reader - The video list<br/>
+/// # Returns
+/// The entries to download and the lines that were rejected, both in the order of the list.
+pub fn read_video_list(reader: impl BufRead) -> (Vec<VideoEntry>, Vec<RejectedLine>) {
+    let mut entries = Vec::new();
+    let mut rejected = Vec::new();
+    for (ix, line) in reader.lines().enumerate() {
+        let line_number = ix + 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                rejected.push(RejectedLine {
+                    line_number,
+                    line: String::new(),
+                    reason: format!("Could not read the line: {e}"),
+                });
+                continue;
+            }
+        };
+        let content = strip_comment(&line).trim();
+        if content.is_empty() {
+            continue;
+        }
+        match parse_line(content, line_number) {
+            Ok(entry) => entries.push(entry),
+            Err(reason) => rejected.push(RejectedLine {
+                line_number,
+                line: line.clone(),
+                reason,
+            }),
+        }
+    }
+    (entries, rejected)
+}
+
+/// Write entries as a video list with the reason for each entry as trailing comment, so the list can be fed back to
+/// yt-parallel with `-l`.
+/// # Parameters
+/// path - The file to write, it is overwritten when it exists<br/>
+/// entries - The entries with the reason they are in the list<br/>
+/// # Returns
+/// Nothing on ok, the error when the file could not be written.
+pub fn write_video_list(path: &Path, entries: &[(VideoEntry, String)]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    for (entry, reason) in entries {
+        // The reason has to stay on one line, otherwise the rest would be read back as entries.
+        let reason = reason.split_whitespace().collect::<Vec<&str>>().join(" ");
+        writeln!(file, "{}   # {}", entry.to_line(), reason)?;
+    }
+    Ok(())
+}
+
+/// Cut the comment off a line, a `#` only starts a comment at the start of the line or after whitespace so the
+/// fragment of a url like `https://example.com/page#part` stays intact.
+fn strip_comment(line: &str) -> &str {
+    if line.trim_start().starts_with('#') {
+        return "";
+    }
+    match line
+        .char_indices()
+        .find(|(ix, c)| *c == '#' && line[..*ix].ends_with([' ', '\t']))
+    {
+        Some((ix, _)) => &line[..ix],
+        None => line,
+    }
+}
+
+/// Check the url of a line and turn the known shorthands into a full url. Accepted are http(s) urls, urls without
+/// the scheme like `youtu.be/abc` and bare YouTube video ids.
+/// # Parameters
+/// text - The url as written in the list
+/// # Returns
+/// The full url, or the reason it is not accepted.
+pub fn normalize_url(text: &str) -> Result<String, String> {
+    if is_youtube_id(text) {
+        return Ok(format!("https://www.youtube.com/watch?v={text}"));
+    }
+    let with_scheme = if text.contains("://") {
+        text.to_string()
+    } else {
+        format!("https://{text}")
+    };
+    let url = Url::parse(&with_scheme).map_err(|e| format!("'{text}' is not a valid url: {e}"))?;
+    // Without a scheme something like `mailto:someone@example.com` would read as a user on a host
+    let other_scheme = !text.contains("://") && !url.username().is_empty();
+    if (url.scheme() != "http" && url.scheme() != "https") || other_scheme {
+        return Err(format!("'{text}' is not a http or https url"));
+    }
+    match url.host_str() {
+        // Without a scheme the host needs a dot, otherwise any word would pass as a url
+        Some(host) if host.contains('.') || text.contains("://") => Ok(with_scheme),
+        _ => Err(format!("'{text}' is not a url or a YouTube video id")),
+    }
+}
+
+/// Query parameters that only track where a link came from, they never change what is downloaded.
+const TRACKING_PARAMETERS: [&str; 7] = ["si", "feature", "fbclid", "gclid", "igshid", "ref", "pp"];
+
+/// The canonical form of a url, two urls with the same canonical form download the same thing. The YouTube shapes
+/// (youtu.be, shorts, live, embed, mobile) become `https://www.youtube.com/watch?v=<id>` without the time and
+/// playlist parameters, tracking parameters are removed from all urls.
+/// # Parameters
+/// url - A url as accepted by `normalize_url`
+/// # Returns
+/// The canonical url, the url itself when it cannot be parsed.
+pub fn canonical_url(url: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_string();
+    };
+    if let Some(id) = youtube_video_id(&parsed) {
+        return format!("https://www.youtube.com/watch?v={id}");
+    }
+    let kept: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_") && !TRACKING_PARAMETERS.contains(&key.as_ref()))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    if kept.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(kept);
+    }
+    if matches!(
+        parsed.host_str(),
+        Some("youtube.com") | Some("m.youtube.com")
+    ) {
+        let _ = parsed.set_host(Some("www.youtube.com"));
+    }
+    parsed.to_string()
+}
+
+/// The id of the YouTube video a url points at, playlists and channels have none.
+/// # Parameters
+/// url - The parsed url
+/// # Returns
+/// The video id, None when the url is not a YouTube video.
+pub fn youtube_video_id(url: &Url) -> Option<String> {
+    let host = url.host_str()?.to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    let mut segments = url.path_segments()?.filter(|s| !s.is_empty());
+    let id = match host {
+        "youtu.be" => segments.next().map(str::to_string),
+        "youtube.com" | "m.youtube.com" | "music.youtube.com" | "youtube-nocookie.com" => {
+            match segments.next() {
+                Some("watch") => url
+                    .query_pairs()
+                    .find(|(key, _)| key == "v")
+                    .map(|(_, value)| value.to_string()),
+                Some("shorts") | Some("live") | Some("embed") | Some("v") => {
+                    segments.next().map(str::to_string)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }?;
+    is_youtube_id(&id).then_some(id)
+}
+
+/// Remove the entries that download the same thing as an earlier entry with the same options, the first one in the
+/// list wins. The same url with other options is kept, as it is asked for in another shape or place, with a warning.
+/// # Parameters
+/// entries - The entries in the order of the list
+/// # Returns
+/// The entries to download, and the entries that were dropped with the line number of the entry they duplicate.
+pub fn dedupe_entries(entries: Vec<VideoEntry>) -> (Vec<VideoEntry>, Vec<(VideoEntry, usize)>) {
+    // The first line of every url, and of every url with its options
+    let mut seen_urls: HashMap<String, usize> = HashMap::new();
+    let mut seen: HashMap<(String, VideoOptions), usize> = HashMap::new();
+    let mut kept = Vec::new();
+    let mut merged = Vec::new();
+    for entry in entries {
+        let url = canonical_url(&entry.url);
+        let key = (
+            url.clone(),
+            (
+                entry.profile.clone(),
+                entry.subdir.clone(),
+                entry.name.clone(),
+            ),
+        );
+        match seen.get(&key) {
+            Some(line_number) => merged.push((entry, *line_number)),
+            None => {
+                if let Some(line_number) = seen_urls.get(&url) {
+                    warn!(
+                        "Line {} ({}) is the same as line {} but with other options, downloading both",
+                        entry.line_number, entry.url, line_number
+                    );
+                }
+                seen_urls.entry(url).or_insert(entry.line_number);
+                seen.insert(key, entry.line_number);
+                kept.push(entry);
+            }
+        }
+    }
+    (kept, merged)
+}
+
+/// The profile, subdir and name options of an entry
+type VideoOptions = (Option<String>, Option<String>, Option<String>);
+
+/// A YouTube video id is 11 characters of letters, digits, `-` and `_`
+fn is_youtube_id(text: &str) -> bool {
+    text.len() == 11
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Whether a path stays inside the folder it is relative to, it has no root and no `..`
+fn is_inside_folder(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// The output template without its `%(...)` fields, `%(uploader)s/%(title)s.%(ext)s` is `s/s.s`
+fn without_template_fields(template: &str) -> String {
+    let mut rest = template;
+    let mut plain = String::new();
+    while let Some(start) = rest.find("%(") {
+        plain.push_str(&rest[..start]);
+        // A field that is not closed is left out up to the end
+        rest = rest[start..]
+            .find(')')
+            .map_or("", |end| &rest[start + end + 1..]);
+    }
+    plain.push_str(rest);
+    plain
+}
+
+/// Parse a line of the video list into an entry.
+/// # Parameters
+/// line - The text of the line<br/>
+/// line_number - The line number, starting at 1<br/>
+/// # Returns
+/// The entry, or a message explaining what is wrong with the options.
+pub fn parse_line(line: &str, line_number: usize) -> Result<VideoEntry, String> {
+    let mut parts = line.split('|');
+    let url = parts.next().unwrap_or_default().trim();
+    if url.is_empty() {
+        return Err(String::from("No url before the options"));
+    }
+    let mut entry = VideoEntry {
+        url: normalize_url(url)?,
+        line_number,
+        ..Default::default()
+    };
+    for option in parts {
+        let option = option.trim();
+        let Some((key, value)) = option.split_once('=') else {
+            return Err(format!("Option '{option}' is not in the key=value form"));
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if value.is_empty() {
+            return Err(format!("Option '{key}' has no value"));
+        }
+        match key {
+            "profile" => entry.profile = Some(value.to_string()),
+            "subdir" => {
+                // Keep the downloads inside the download folder, it is moved as a whole later on.
+                if !is_inside_folder(value) {
+                    return Err(format!(
+                        "Subdir '{value}' must be a relative path inside the download folder"
+                    ));
+                }
+                entry.subdir = Some(value.to_string())
+            }
+            "name" => {
+                // The name can hold folders as well, the fields of the tool are filled in with names of its own.
+                if !is_inside_folder(&without_template_fields(value)) {
+                    return Err(format!(
+                        "Name '{value}' must be a relative path inside the download folder"
+                    ));
+                }
+                entry.name = Some(value.to_string())
+            }
+            _ => return Err(format!("Unknown option '{key}'")),
+        }
+    }
+    Ok(entry)
+}
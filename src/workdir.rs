@@ -0,0 +1,205 @@
+use crate::format_date_pattern;
+use crate::state::STATE_FILE_NAME;
+use chrono::{DateTime, Local};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+/// What to do when the download folder, or the folder at the move target, already exists
+#[derive(clap::ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollisionPolicy {
+    /// Use the name with a counter after it, like `20240101-2`
+    Suffix,
+    /// Use the folder that is there, the files go in next to the ones already in it
+    Merge,
+    /// Stop, the folder that is there is left alone
+    Fail,
+}
+
+impl CollisionPolicy {
+    /// The name of the policy as it is written in the config and on the commandline
+    pub fn name(&self) -> &'static str {
+        match self {
+            CollisionPolicy::Suffix => "suffix",
+            CollisionPolicy::Merge => "merge",
+            CollisionPolicy::Fail => "fail",
+        }
+    }
+}
+
+/// The folder with a counter after its name, `20240101` with 2 is `20240101-2`.
+pub fn suffixed_path(path: &Path, counter: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!("-{counter}"));
+    path.with_file_name(name)
+}
+
+/// Create the download folder, what happens when it is already there is up to the policy.
+/// # Parameters
+/// path - The download folder<br/>
+/// policy - What to do when the folder exists<br/>
+/// # Returns
+/// The folder that was created or is used, or an error message when it exists and the policy is fail or the folder
+/// could not be created.
+pub fn create_download_folder(path: &Path, policy: CollisionPolicy) -> Result<PathBuf, String> {
+    let mut candidate = path.to_path_buf();
+    let mut counter = 1;
+    loop {
+        // Creating the folder is the check, two runs starting at the same time cannot both get the same name.
+        match fs::create_dir(&candidate) {
+            Ok(()) => return Ok(candidate),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => match policy {
+                CollisionPolicy::Merge if candidate.is_dir() => return Ok(candidate),
+                CollisionPolicy::Suffix | CollisionPolicy::Merge => {
+                    counter += 1;
+                    candidate = suffixed_path(path, counter);
+                }
+                CollisionPolicy::Fail => {
+                    return Err(format!(
+                        "The download folder {} already exists",
+                        path.display()
+                    ));
+                }
+            },
+            Err(e) => {
+                return Err(format!(
+                    "Could not create the download folder {}: {}",
+                    candidate.display(),
+                    e
+                ));
+            }
+        }
+    }
+}
+
+/// Find the batch to go on with for a `--resume` without a folder. That is the download folder of today, or the one
+/// with the highest counter after its name when the collision policy made more, as long as it has a state file.
+/// # Parameters
+/// path - The download folder the name template gives, in the work dir<br/>
+/// # Returns
+/// The newest download folder with a state file, or an error message when there is none.
+pub fn resumable_folder(path: &Path) -> Result<PathBuf, String> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut candidates: Vec<(usize, PathBuf)> = fs::read_dir(parent)
+        .map_err(|e| {
+            format!(
+                "Could not look for a batch to resume in {}: {e}",
+                parent.display()
+            )
+        })?
+        .filter_map(|entry| {
+            let file_name = entry.ok()?.file_name().to_string_lossy().to_string();
+            let counter = match file_name.strip_prefix(name.as_ref())? {
+                "" => 1,
+                rest => rest.strip_prefix('-')?.parse().ok().filter(|c| *c > 1)?,
+            };
+            Some((counter, suffixed_or_plain(path, counter)))
+        })
+        .filter(|(_, folder)| folder.join(STATE_FILE_NAME).is_file())
+        .collect();
+    candidates.sort();
+    let Some((_, newest)) = candidates.pop() else {
+        return Err(format!(
+            "There is no batch to resume in {} or the folders with a counter after it, pass the folder of the batch \
+            to --resume",
+            path.display()
+        ));
+    };
+    if !candidates.is_empty() {
+        let others: Vec<String> = candidates
+            .iter()
+            .map(|(_, folder)| folder.display().to_string())
+            .collect();
+        warn!(
+            "Resuming the newest batch {}, pass the folder to --resume to go on with one of {}",
+            newest.display(),
+            others.join(", ")
+        );
+    }
+    Ok(newest)
+}
+
+/// The folder itself for counter 1, else the folder with the counter after its name
+fn suffixed_or_plain(path: &Path, counter: usize) -> PathBuf {
+    if counter == 1 {
+        path.to_path_buf()
+    } else {
+        suffixed_path(path, counter)
+    }
+}
+
+/// The values of the `{token}` placeholders in the name of the download folder
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderTokens {
+    /// The file name of the video list without its extension, like `videolist`
+    pub list_name: String,
+    /// The id of the run, the same as in the log
+    pub run_id: String,
+    pub hostname: String,
+}
+
+/// Render the name of the download folder from its template, the strftime patterns like `%Y%m%d` are filled in first
+/// and then the tokens `{list_name}`, `{run_id}` and `{hostname}`.
+/// # Parameters
+/// template - The template, like `%Y%m%d-{list_name}`<br/>
+/// datetime - The date and time to fill in<br/>
+/// tokens - The values of the tokens<br/>
+/// # Returns
+/// The folder name, or an error message when the template has an unknown token or the name would not be a single
+/// folder.
+pub fn render_folder_name(
+    template: &str,
+    datetime: &DateTime<Local>,
+    tokens: &FolderTokens,
+) -> Result<String, String> {
+    // The date goes first, a `%` in the name of a list would otherwise be taken for a date pattern.
+    let mut name = format_date_pattern(template, datetime)?;
+    for (token, value) in [
+        ("{list_name}", &tokens.list_name),
+        ("{run_id}", &tokens.run_id),
+        ("{hostname}", &tokens.hostname),
+    ] {
+        name = name.replace(token, &value.replace(['/', '\\'], "_"));
+    }
+    if let Some(start) = name.find('{') {
+        let token = name[start..]
+            .split_inclusive('}')
+            .next()
+            .unwrap_or_default();
+        return Err(format!(
+            "'{template}' has an unknown token {token}, use {{list_name}}, {{run_id}} or {{hostname}}"
+        ));
+    }
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(format!(
+            "'{template}' does not give a folder name, it gives '{name}'"
+        ));
+    }
+    Ok(name)
+}
+
+/// The name of the machine, for the `{hostname}` token
+pub fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buffer = [0u8; 256];
+        // SAFETY: the buffer is valid for its length, gethostname writes at most that many bytes into it.
+        let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+        if result == 0 {
+            let end = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+            let name = String::from_utf8_lossy(&buffer[..end]).to_string();
+            if !name.is_empty() {
+                return name;
+            }
+        }
+    }
+    env::var("COMPUTERNAME")
+        .or_else(|_| env::var("HOSTNAME"))
+        .unwrap_or_else(|_| String::from("localhost"))
+}